@@ -0,0 +1,76 @@
+// Compares the boxed BstMap with the arena backed ArenaBstMap.
+//
+// Run with `cargo bench`. Each workload runs on both maps with the same
+// pseudo-random keys, and reports the average time per operation.
+
+use std::{hint::black_box, time::Instant};
+use bstmap::{ArenaBstMap, BstMap};
+
+const SIZES: [usize; 3] = [1_000, 100_000, 1_000_000];
+
+// xorshift64, so both maps see the same keys on every run.
+fn keys(count: usize) -> Vec<u64> {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+        .collect()
+}
+
+fn report(workload: &str, size: usize, storage: &str, start: Instant, ops: usize) {
+    let nanos = start.elapsed().as_nanos() as f64 / ops as f64;
+    println!("{:<10} {:>9} {:<6} {:>9.1} ns/op", workload, size, storage, nanos);
+}
+
+// The same workloads for both maps. Their interfaces match, so this
+// only has to paper over the types.
+macro_rules! bench_map {
+    ($storage:expr, $map:ty, $size:expr, $keys:expr) => {{
+        let keys: &[u64] = $keys;
+
+        let start = Instant::now();
+        let mut map = <$map>::new();
+        for &key in keys {
+            map.insert(key, key);
+        }
+        report("insert", $size, $storage, start, keys.len());
+
+        let start = Instant::now();
+        for key in keys {
+            black_box(map.get(key));
+        }
+        report("get", $size, $storage, start, keys.len());
+
+        // Removes and reinserts half the keys, the allocator churn
+        // the arena is meant to avoid.
+        let start = Instant::now();
+        for &key in keys.iter().step_by(2) {
+            black_box(map.remove(&key));
+            map.insert(key, key);
+        }
+        report("churn", $size, $storage, start, keys.len());
+
+        let start = Instant::now();
+        black_box(map.iter().map(|(_, value)| *value).fold(0, u64::wrapping_add));
+        report("iter", $size, $storage, start, keys.len());
+
+        let start = Instant::now();
+        for key in keys {
+            black_box(map.remove(key));
+        }
+        report("remove", $size, $storage, start, keys.len());
+    }};
+}
+
+fn main() {
+    for size in SIZES {
+        let keys = keys(size);
+        bench_map!("boxed", BstMap<u64, u64>, size, &keys);
+        bench_map!("arena", ArenaBstMap<u64, u64>, size, &keys);
+        println!();
+    }
+}
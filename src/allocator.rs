@@ -0,0 +1,120 @@
+use std::{alloc::Layout, mem::MaybeUninit};
+use crate::node::Node;
+use crate::pool::{TryReserveError, TryReserveErrorKind};
+
+// Allocator support for BstMap.
+//
+// Every Node is boxed in the map's allocator A. With the allocator_api
+// feature, which needs a nightly compiler, A can be any std Allocator and
+// Nodes are Box<Node, A>. Without it, the Allocator and Global below stand
+// in for the std ones, Global being the only allocator there is, and Nodes
+// are plain Boxes.
+//
+// Either way the rest of the crate is written once, generic over A, and
+// goes through the functions in here whenever it boxes or unboxes a Node.
+
+#[cfg(feature = "allocator_api")]
+pub use std::alloc::{Allocator, Global};
+
+#[cfg(not(feature = "allocator_api"))]
+pub use self::stable::{Allocator, Global};
+
+#[cfg(not(feature = "allocator_api"))]
+mod stable {
+    /// The allocator a `BstMap` boxes its nodes in.
+    ///
+    /// Custom allocators need the `allocator_api` feature, and with it a
+    /// nightly compiler. Without it this stands in for
+    /// `std::alloc::Allocator`, and `Global` is the only allocator.
+    pub trait Allocator: private::Sealed {}
+
+    /// The global memory allocator.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Global;
+
+    impl Allocator for Global {}
+
+    mod private {
+        pub trait Sealed {}
+        impl Sealed for super::Global {}
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+pub type NodeBox<T, V, A> = Box<Node<T, V, A>, A>;
+#[cfg(not(feature = "allocator_api"))]
+pub type NodeBox<T, V, A> = Box<Node<T, V, A>>;
+
+// A Node allocation with nothing in it.
+#[cfg(feature = "allocator_api")]
+pub type Spare<T, V, A> = Box<MaybeUninit<Node<T, V, A>>, A>;
+#[cfg(not(feature = "allocator_api"))]
+pub type Spare<T, V, A> = Box<MaybeUninit<Node<T, V, A>>>;
+
+// Boxes node in alloc.
+#[cfg(feature = "allocator_api")]
+pub fn boxed<T, V, A: Allocator + Clone>(node: Node<T, V, A>, alloc: &A) -> NodeBox<T, V, A> {
+    Box::new_in(node, alloc.clone())
+}
+
+#[cfg(not(feature = "allocator_api"))]
+pub fn boxed<T, V, A: Allocator + Clone>(node: Node<T, V, A>, _alloc: &A) -> NodeBox<T, V, A> {
+    Box::new(node)
+}
+
+// Allocates room for a Node in alloc.
+#[cfg(feature = "allocator_api")]
+pub fn spare<T, V, A: Allocator + Clone>(alloc: &A) -> Spare<T, V, A> {
+    Box::new_uninit_in(alloc.clone())
+}
+
+#[cfg(not(feature = "allocator_api"))]
+pub fn spare<T, V, A: Allocator + Clone>(_alloc: &A) -> Spare<T, V, A> {
+    Box::new_uninit()
+}
+
+// Same as spare, but reports allocation failure instead of aborting.
+#[cfg(feature = "allocator_api")]
+pub fn try_spare<T, V, A: Allocator + Clone>(alloc: &A) -> Result<Spare<T, V, A>, TryReserveError> {
+    Box::try_new_uninit_in(alloc.clone())
+        .map_err(|_| TryReserveErrorKind::AllocError { layout: Layout::new::<Node<T, V, A>>() }.into())
+}
+
+#[cfg(not(feature = "allocator_api"))]
+pub fn try_spare<T, V, A: Allocator + Clone>(_alloc: &A) -> Result<Spare<T, V, A>, TryReserveError> {
+    // Node always holds a height and size, so it is never zero sized.
+    let layout = Layout::new::<Node<T, V, A>>();
+    // Safety: layout has a non-zero size.
+    let raw = unsafe { std::alloc::alloc(layout) };
+    if raw.is_null() {
+        return Err(TryReserveErrorKind::AllocError { layout }.into());
+    }
+    // Safety: raw was allocated by the global allocator
+    // with the layout of a Node.
+    Ok(unsafe { Box::from_raw(raw.cast()) })
+}
+
+// Moves node into a spare allocation.
+pub fn fill<T, V, A>(mut spare: Spare<T, V, A>, node: Node<T, V, A>) -> NodeBox<T, V, A>
+        where A: Allocator + Clone {
+
+    spare.write(node);
+    // Safety: spare was just initialized.
+    unsafe { spare.assume_init() }
+}
+
+// Moves the Node out of its Box, keeping the allocation as a spare.
+#[cfg(feature = "allocator_api")]
+pub fn empty<T, V, A: Allocator + Clone>(node: NodeBox<T, V, A>) -> (Node<T, V, A>, Spare<T, V, A>) {
+    let (raw, alloc) = Box::into_raw_with_allocator(node);
+    // Safety: raw came from a Box, and is read exactly once. Afterwards
+    // its memory only ever gets treated as uninitialized.
+    unsafe { (raw.read(), Box::from_raw_in(raw.cast(), alloc)) }
+}
+
+#[cfg(not(feature = "allocator_api"))]
+pub fn empty<T, V, A: Allocator + Clone>(node: NodeBox<T, V, A>) -> (Node<T, V, A>, Spare<T, V, A>) {
+    let raw = Box::into_raw(node);
+    // Safety: see above.
+    unsafe { (raw.read(), Box::from_raw(raw.cast())) }
+}
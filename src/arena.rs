@@ -0,0 +1,438 @@
+use std::{borrow::Borrow, cmp::Ordering, fmt, iter::FusedIterator, mem};
+
+// Arena backed alternative to BstMap.
+// Nodes live side by side in a single Vec and point at each other by u32
+// index instead of by Box, so inserting only allocates when the Vec grows,
+// and neighbouring Nodes tend to share cache lines. Removed Nodes leave
+// their slot on a free list, which the next insert reuses.
+//
+// The tree is balanced the same way as BstMap's. Without parent pointers,
+// inserts and removes record the indices they went through on the way
+// down, then rebalance along them on the way back up, much like Path.
+
+// Index standing in for a missing Node.
+pub const NIL: u32 = u32::MAX;
+
+pub struct ArenaNode<T, V> {
+    pub key: T,
+    pub value: V,
+    pub left: u32,
+    pub right: u32,
+    pub height: u8,
+}
+
+pub enum Slot<T, V> {
+    Occupied(ArenaNode<T, V>),
+    // Next slot on the free list.
+    Vacant(u32),
+}
+
+/// A map implemented with a binary search tree whose nodes
+/// are stored together in a single contiguous arena.
+///
+/// Offers the core of the `BstMap` interface. Prefer it for maps that
+/// see many inserts and removes in hot loops: nodes are not allocated
+/// one by one, and memory freed by removes is reused by later inserts.
+///
+/// Keys are always ordered by their `Ord` implementation; unlike
+/// `BstMap`, there is no way to supply a custom comparator.
+///
+/// ```
+/// use bstmap::ArenaBstMap;
+///
+/// let mut map = ArenaBstMap::new();
+/// map.insert("ten", 10);
+/// map.insert("one", 1);
+/// assert!(map.get("ten") == Some(&10));
+/// assert!(map.remove("one") == Some(1));
+/// assert!(map.iter().eq([(&"ten", &10)]));
+/// ```
+pub struct ArenaBstMap<T, V> {
+    pub(crate) slots: Vec<Slot<T, V>>,
+    pub(crate) root: u32,
+    // Head of the free list.
+    free: u32,
+    len: usize,
+    // Scratch space for the path of inserts and removes,
+    // kept around so they don't allocate.
+    path: Vec<(u32, bool)>,
+}
+
+impl<T, V> ArenaBstMap<T, V> {
+    /// Creates an empty `ArenaBstMap`.
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Creates an empty `ArenaBstMap` with room for
+    /// at least `capacity` entries before it reallocates.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            root: NIL,
+            free: NIL,
+            len: 0,
+            path: Vec::new(),
+        }
+    }
+
+    /// Clears all entries from map, keeping the arena's memory.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.root = NIL;
+        self.free = NIL;
+        self.len = 0;
+    }
+
+    /// Returns `true` if map is empty.
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Returns the number of entries in map.
+    pub fn len(&self) -> usize { self.len }
+
+    /// Returns the number of entries map can hold without reallocating.
+    pub fn capacity(&self) -> usize { self.slots.capacity() }
+
+    /// Returns an `Iterator` over references to the contents of map,
+    /// yielding key/value pairs in ascending key order.
+    pub fn iter(&self) -> ArenaIter<'_, T, V> {
+        let mut iter = ArenaIter {
+            map: self,
+            stack: Vec::new(),
+            len: self.len,
+        };
+        iter.push_left(self.root);
+        iter
+    }
+
+    /// Returns the key/value pair with the smallest key,
+    /// or `None` if map is empty.
+    pub fn first_key_value(&self) -> Option<(&T, &V)> {
+        let mut index = self.root;
+        if index == NIL { return None }
+        while self.node(index).left != NIL {
+            index = self.node(index).left;
+        }
+        let node = self.node(index);
+        Some((&node.key, &node.value))
+    }
+
+    /// Returns the key/value pair with the largest key,
+    /// or `None` if map is empty.
+    pub fn last_key_value(&self) -> Option<(&T, &V)> {
+        let mut index = self.root;
+        if index == NIL { return None }
+        while self.node(index).right != NIL {
+            index = self.node(index).right;
+        }
+        let node = self.node(index);
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<T: Ord, V> ArenaBstMap<T, V> {
+    /// Inserts a key/value pair into map.
+    /// If key exists, existing value is clobbered and returned
+    /// as `Some(value)`.
+    pub fn insert(&mut self, key: T, value: V) -> Option<V> {
+        // (Node passed through, whether we went left from it)
+        let mut path = mem::take(&mut self.path);
+        path.clear();
+        let mut index = self.root;
+        while index != NIL {
+            let node = self.node_mut(index);
+            match key.cmp(&node.key) {
+                Ordering::Less => {
+                    path.push((index, true));
+                    index = node.left;
+                }
+                Ordering::Greater => {
+                    path.push((index, false));
+                    index = node.right;
+                }
+                Ordering::Equal => {
+                    let old = mem::replace(&mut node.value, value);
+                    self.path = path;
+                    return Some(old);
+                }
+            }
+        }
+
+        let index = self.allocate(ArenaNode {
+            key,
+            value,
+            left: NIL,
+            right: NIL,
+            height: 1,
+        });
+        self.len += 1;
+        self.retrace(path, index);
+        None
+    }
+
+    /// Returns `Some(&value)` associated with key,
+    /// or `None` if key wasn't found.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+            where T: Borrow<Q>, Q: Ord + ?Sized {
+
+        let index = self.find(key)?;
+        Some(&self.node(index).value)
+    }
+
+    /// Returns `Some(&mut value)` associated with key,
+    /// or `None` if key wasn't found.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+            where T: Borrow<Q>, Q: Ord + ?Sized {
+
+        let index = self.find(key)?;
+        Some(&mut self.node_mut(index).value)
+    }
+
+    /// Removes the entry matching key, returning its value,
+    /// or `None` if key wasn't found.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+            where T: Borrow<Q>, Q: Ord + ?Sized {
+
+        let mut path = mem::take(&mut self.path);
+        path.clear();
+        let mut index = self.root;
+        loop {
+            if index == NIL {
+                self.path = path;
+                return None;
+            }
+            let node = self.node(index);
+            match key.cmp(node.key.borrow()) {
+                Ordering::Less => {
+                    path.push((index, true));
+                    index = node.left;
+                }
+                Ordering::Greater => {
+                    path.push((index, false));
+                    index = node.right;
+                }
+                Ordering::Equal => break,
+            }
+        }
+
+        // With two children, our pair is swapped with that of our
+        // successor, which has no left child and is removed instead.
+        let (left, right) = (self.node(index).left, self.node(index).right);
+        if left != NIL && right != NIL {
+            path.push((index, false));
+            let mut successor = right;
+            while self.node(successor).left != NIL {
+                path.push((successor, true));
+                successor = self.node(successor).left;
+            }
+            self.swap_pairs(index, successor);
+            index = successor;
+        }
+
+        let node = self.node(index);
+        let child = if node.left != NIL { node.left } else { node.right };
+        let node = self.deallocate(index);
+        self.len -= 1;
+        self.retrace(path, child);
+        Some(node.value)
+    }
+
+    // Index of the Node matching key.
+    fn find<Q>(&self, key: &Q) -> Option<u32>
+            where T: Borrow<Q>, Q: Ord + ?Sized {
+
+        let mut index = self.root;
+        while index != NIL {
+            let node = self.node(index);
+            match key.cmp(node.key.borrow()) {
+                Ordering::Less => index = node.left,
+                Ordering::Greater => index = node.right,
+                Ordering::Equal => return Some(index),
+            }
+        }
+        None
+    }
+
+}
+
+impl<T, V> ArenaBstMap<T, V> {
+    pub(crate) fn node(&self, index: u32) -> &ArenaNode<T, V> {
+        match &self.slots[index as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => unreachable!("index points at a free slot"),
+        }
+    }
+
+    fn node_mut(&mut self, index: u32) -> &mut ArenaNode<T, V> {
+        match &mut self.slots[index as usize] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant(_) => unreachable!("index points at a free slot"),
+        }
+    }
+
+    fn height(&self, index: u32) -> u8 {
+        if index == NIL { 0 } else { self.node(index).height }
+    }
+
+    // Places node in a free slot, or a new one if there are none.
+    fn allocate(&mut self, node: ArenaNode<T, V>) -> u32 {
+        if self.free != NIL {
+            let index = self.free;
+            match mem::replace(&mut self.slots[index as usize], Slot::Occupied(node)) {
+                Slot::Vacant(next) => self.free = next,
+                Slot::Occupied(_) => unreachable!("free list points at an occupied slot"),
+            }
+            index
+        } else {
+            // NIL itself is never handed out.
+            assert!(self.slots.len() < NIL as usize, "ArenaBstMap can't hold more than u32::MAX - 1 entries");
+            self.slots.push(Slot::Occupied(node));
+            (self.slots.len() - 1) as u32
+        }
+    }
+
+    // Frees the slot at index, handing back the Node that was in it.
+    fn deallocate(&mut self, index: u32) -> ArenaNode<T, V> {
+        match mem::replace(&mut self.slots[index as usize], Slot::Vacant(self.free)) {
+            Slot::Occupied(node) => {
+                self.free = index;
+                node
+            }
+            Slot::Vacant(_) => unreachable!("index points at a free slot"),
+        }
+    }
+
+    fn swap_pairs(&mut self, a: u32, b: u32) {
+        let (low, high) = (a.min(b) as usize, a.max(b) as usize);
+        let (front, back) = self.slots.split_at_mut(high);
+        if let (Slot::Occupied(a), Slot::Occupied(b)) = (&mut front[low], &mut back[0]) {
+            mem::swap(&mut a.key, &mut b.key);
+            mem::swap(&mut a.value, &mut b.value);
+        }
+    }
+
+    // Walks back up path after the subtree below its last Node changed,
+    // child being that subtree's new root. Every Node on the way is
+    // rebalanced and relinked to its parent.
+    // Hands path back for reuse when done.
+    fn retrace(&mut self, path: Vec<(u32, bool)>, mut child: u32) {
+        for &(index, went_left) in path.iter().rev() {
+            let node = self.node_mut(index);
+            if went_left { node.left = child } else { node.right = child }
+            child = self.rebalance(index);
+        }
+        self.root = child;
+        self.path = path;
+    }
+
+    fn update(&mut self, index: u32) {
+        let node = self.node(index);
+        let height = 1 + self.height(node.left).max(self.height(node.right));
+        self.node_mut(index).height = height;
+    }
+
+    fn balance(&self, index: u32) -> i16 {
+        let node = self.node(index);
+        self.height(node.left) as i16 - self.height(node.right) as i16
+    }
+
+    // Same as Node::rebalance. Returns the subtree's new root.
+    fn rebalance(&mut self, index: u32) -> u32 {
+        self.update(index);
+        match self.balance(index) {
+            2 => {
+                let left = self.node(index).left;
+                if self.balance(left) < 0 {
+                    self.node_mut(index).left = self.rotate_left(left);
+                }
+                self.rotate_right(index)
+            }
+            -2 => {
+                let right = self.node(index).right;
+                if self.balance(right) > 0 {
+                    self.node_mut(index).right = self.rotate_right(right);
+                }
+                self.rotate_left(index)
+            }
+            _ => index,
+        }
+    }
+
+    // Our right child takes our place and we become its left child.
+    fn rotate_left(&mut self, index: u32) -> u32 {
+        let right = self.node(index).right;
+        self.node_mut(index).right = self.node(right).left;
+        self.node_mut(right).left = index;
+        self.update(index);
+        self.update(right);
+        right
+    }
+
+    // Our left child takes our place and we become its right child.
+    fn rotate_right(&mut self, index: u32) -> u32 {
+        let left = self.node(index).left;
+        self.node_mut(index).left = self.node(left).right;
+        self.node_mut(left).right = index;
+        self.update(index);
+        self.update(left);
+        left
+    }
+}
+
+impl<T, V> Default for ArenaBstMap<T, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug, V: fmt::Debug> fmt::Debug for ArenaBstMap<T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// `Iterator` over references to the contents of an `ArenaBstMap`,
+/// yielding key/value pairs in ascending key order.
+pub struct ArenaIter<'a, T, V> {
+    map: &'a ArenaBstMap<T, V>,
+    stack: Vec<u32>,
+    len: usize,
+}
+
+impl<T, V> ArenaIter<'_, T, V> {
+    // Pushes index and its chain of left children onto the stack.
+    fn push_left(&mut self, mut index: u32) {
+        while index != NIL {
+            self.stack.push(index);
+            index = self.map.node(index).left;
+        }
+    }
+}
+
+impl<'a, T, V> Iterator for ArenaIter<'a, T, V> {
+    type Item = (&'a T, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.stack.pop()?;
+        self.len -= 1;
+        let node = self.map.node(index);
+        self.push_left(node.right);
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T, V> ExactSizeIterator for ArenaIter<'_, T, V> {}
+impl<T, V> FusedIterator for ArenaIter<'_, T, V> {}
+
+impl<'a, T, V> IntoIterator for &'a ArenaBstMap<T, V> {
+    type Item = (&'a T, &'a V);
+    type IntoIter = ArenaIter<'a, T, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
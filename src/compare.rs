@@ -0,0 +1,74 @@
+use std::cmp::Ordering;
+
+// Key ordering for BstMap.
+//
+// Every descent through the tree asks the map's comparator which way to
+// turn, instead of calling Ord::cmp on the keys directly. Natural is the
+// default and just defers to Ord, so a BstMap that never names a
+// comparator behaves exactly as if keys were compared with cmp.
+//
+// A comparator must be a total order that never changes for as long as
+// the map holds keys. Like with a key whose Ord changes while it is in
+// the map, breaking that leaves the map unspecified but memory safe.
+
+/// A total order over keys of type `K`, used by a `BstMap` in place of
+/// `K`'s `Ord` implementation.
+///
+/// Implemented by [`Natural`], [`Reverse`], and any closure or function of
+/// type `Fn(&K, &K) -> Ordering`.
+///
+/// # Examples
+/// ```
+/// # use bstmap::BstMap;
+/// # use std::cmp::Ordering;
+/// let mut map = BstMap::with_comparator(|a: &&str, b: &&str| {
+///     a.to_lowercase().cmp(&b.to_lowercase())
+/// });
+/// map.insert("b", 2);
+/// map.insert("A", 1);
+/// map.insert("B", 3);
+/// assert!(map.len() == 2);
+/// assert!(map.get(&"a") == Some(&1));
+/// assert!(map.iter().collect::<Vec<_>>() == vec![(&"A", &1), (&"b", &3)]);
+/// ```
+pub trait Compare<K: ?Sized> {
+    /// Compares `a` to `b`.
+    fn compare(&self, a: &K, b: &K) -> Ordering;
+}
+
+/// Orders keys by their `Ord` implementation. The default comparator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Natural;
+
+impl<K: Ord + ?Sized> Compare<K> for Natural {
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Reverses the order of another comparator, `Natural` by default.
+///
+/// # Examples
+/// ```
+/// # use bstmap::{BstMap, Natural, Reverse};
+/// let mut map = BstMap::with_comparator(Reverse(Natural));
+/// map.extend([(1, 'a'), (3, 'c'), (2, 'b')]);
+/// assert!(map.iter().map(|(k, _)| *k).collect::<Vec<_>>() == [3, 2, 1]);
+/// assert!(map.first_key_value() == Some((&3, &'c')));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Reverse<C = Natural>(pub C);
+
+impl<K: ?Sized, C: Compare<K>> Compare<K> for Reverse<C> {
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        self.0.compare(b, a)
+    }
+}
+
+impl<K: ?Sized, F> Compare<K> for F
+        where F: Fn(&K, &K) -> Ordering {
+
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        self(a, b)
+    }
+}
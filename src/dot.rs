@@ -0,0 +1,135 @@
+use std::{fmt::{self, Debug, Write as _}, io::{self, Write}};
+use crate::allocator::Allocator;
+use crate::node::{Node, NodeLink};
+
+// Graphviz DOT export for BstMap.
+// Nodes are named after the order they are written in, n0 being the root.
+// Missing children get a point shaped placeholder each, so the left and
+// right children of every Node keep their sides once dot lays them out.
+
+/// Options for `BstMap::to_dot_with`.
+///
+/// By default every node is labelled with its key and value only.
+#[derive(Debug, Clone)]
+pub struct DotOptions {
+    values: bool,
+    height: bool,
+    size: bool,
+    balance: bool,
+}
+
+impl DotOptions {
+    /// Creates `DotOptions` with values shown and no annotations.
+    pub fn new() -> Self {
+        Self {
+            values: true,
+            height: false,
+            size: false,
+            balance: false,
+        }
+    }
+
+    /// Sets whether values are shown next to their keys.
+    pub fn values(mut self, show: bool) -> Self {
+        self.values = show;
+        self
+    }
+
+    /// Sets whether nodes are annotated with their height.
+    /// Leaves have a height of 1.
+    pub fn height(mut self, show: bool) -> Self {
+        self.height = show;
+        self
+    }
+
+    /// Sets whether nodes are annotated with the number
+    /// of nodes in their subtree, themselves included.
+    pub fn size(mut self, show: bool) -> Self {
+        self.size = show;
+        self
+    }
+
+    /// Sets whether nodes are annotated with their balance factor,
+    /// the height of their left subtree minus that of their right.
+    pub fn balance(mut self, show: bool) -> Self {
+        self.balance = show;
+        self
+    }
+
+    fn label<T, V, A>(&self, node: &Node<T, V, A>) -> Result<String, fmt::Error>
+            where T: Debug, V: Debug, A: Allocator + Clone {
+
+        let mut label = String::new();
+        write!(label, "{:?}", node.key)?;
+        if self.values {
+            write!(label, ": {:?}", node.value)?;
+        }
+
+        let mut notes = Vec::new();
+        if self.height { notes.push(format!("h={}", node.height)); }
+        if self.size { notes.push(format!("size={}", node.size)); }
+        if self.balance {
+            notes.push(format!("bf={:+}", node.balance_factor()));
+        }
+        if !notes.is_empty() {
+            write!(label, "\n{}", notes.join(" "))?;
+        }
+        Ok(label)
+    }
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Escapes text for use inside a double quoted DOT string.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Writes the tree under head as a DOT digraph.
+pub(crate) fn write_dot<T, V, A, W>(head: &NodeLink<T, V, A>, mut w: W, options: &DotOptions) -> io::Result<()>
+        where T: Debug, V: Debug, A: Allocator + Clone, W: Write {
+
+    writeln!(w, "digraph BstMap {{")?;
+    writeln!(w, "    node [shape=box];")?;
+
+    // Every name is handed out once, to a Node or a placeholder.
+    let mut next_id = 0;
+    let mut stack = Vec::new();
+    if let Some(head) = head.as_deref() {
+        stack.push((head, next_id));
+        next_id += 1;
+    }
+    while let Some((node, id)) = stack.pop() {
+        // A failing Debug impl is reported as an io::Error rather than a panic.
+        let label = options.label(node).map_err(io::Error::other)?;
+        writeln!(w, "    n{} [label=\"{}\"];", id, escape(&label))?;
+
+        let mut children = Vec::new();
+        for (child, side) in [(&node.left, "L"), (&node.right, "R")] {
+            let child_id = next_id;
+            next_id += 1;
+            match child.as_deref() {
+                Some(child) => children.push((child, child_id)),
+                None => writeln!(w, "    n{} [shape=point];", child_id)?,
+            }
+            writeln!(w, "    n{} -> n{} [label=\"{}\"];", id, child_id, side)?;
+        }
+        // Right is pushed first so left pops first.
+        stack.extend(children.into_iter().rev());
+    }
+
+    writeln!(w, "}}")
+}
@@ -0,0 +1,315 @@
+use std::{error::Error, fmt::{self, Debug, Display}, marker::PhantomData};
+use crate::BstMap;
+use crate::allocator::{Allocator, Global};
+use crate::compare::Compare;
+use crate::node::{Node, NodeLink, NodePosition, Path, LinkPtr};
+use crate::pool::{NodePool, TryReserveError};
+
+// Entry API for BstMap.
+// BstMap::entry descends the tree once with Node::seek and hands the
+// recorded Path and link over to the entry. Inserting or removing through
+// the entry then works straight off that link and retraces the Path,
+// without searching for the key again.
+
+/// A view into a single entry of a `BstMap`,
+/// which may either be vacant or occupied.
+///
+/// Constructed with `BstMap::entry`.
+pub enum Entry<'a, T, V, A: Allocator + Clone = Global> {
+    Vacant(VacantEntry<'a, T, V, A>),
+    Occupied(OccupiedEntry<'a, T, V, A>),
+}
+
+/// A view into a vacant entry of a `BstMap`.
+pub struct VacantEntry<'a, T, V, A: Allocator + Clone = Global> {
+    key: T,
+    path: Path<T, V, A>,
+    // Empty link the new Node goes into.
+    link: LinkPtr<T, V, A>,
+    len: &'a mut usize,
+    pool: &'a mut NodePool<T, V, A>,
+    marker: PhantomData<&'a mut NodeLink<T, V, A>>,
+}
+
+/// A view into an occupied entry of a `BstMap`.
+pub struct OccupiedEntry<'a, T, V, A: Allocator + Clone = Global> {
+    path: Path<T, V, A>,
+    // Link holding the Node this entry refers to. Never empty.
+    link: LinkPtr<T, V, A>,
+    len: &'a mut usize,
+    pool: &'a mut NodePool<T, V, A>,
+    marker: PhantomData<&'a mut NodeLink<T, V, A>>,
+}
+
+// Safety: a VacantEntry owns its key, and otherwise can only reach
+// the map the same ways &'a mut BstMap can.
+unsafe impl<T: Send, V: Send, A: Allocator + Clone + Send> Send for VacantEntry<'_, T, V, A> {}
+unsafe impl<T: Sync, V: Sync, A: Allocator + Clone + Sync> Sync for VacantEntry<'_, T, V, A> {}
+// Safety: an OccupiedEntry can only reach the map the same
+// ways &'a mut BstMap can.
+unsafe impl<T: Send, V: Send, A: Allocator + Clone + Send> Send for OccupiedEntry<'_, T, V, A> {}
+unsafe impl<T: Sync, V: Sync, A: Allocator + Clone + Sync> Sync for OccupiedEntry<'_, T, V, A> {}
+
+/// The error returned by `BstMap::try_insert` when the key already exists.
+///
+/// Holds the occupied entry, and the value that was not inserted.
+pub struct OccupiedError<'a, T, V, A: Allocator + Clone = Global> {
+    /// The entry in the map that was already occupied.
+    pub entry: OccupiedEntry<'a, T, V, A>,
+    /// The value which was not inserted.
+    pub value: V,
+}
+
+impl<'a, T, V, A: Allocator + Clone> Entry<'a, T, V, A> {
+    // Seeks key in map and wraps up whatever was found.
+    pub(crate) fn new<C: Compare<T>>(map: &'a mut BstMap<T, V, C, A>, key: T) -> Self {
+        let (path, link) = Node::seek(&mut map.head, &key, &map.cmp);
+        // Safety: link was just recorded from map.head, which stays
+        // borrowed for 'a.
+        if unsafe { (*link).is_some() } {
+            Entry::Occupied(OccupiedEntry {
+                path, link, len: &mut map.len, pool: &mut map.pool, marker: PhantomData,
+            })
+        } else {
+            Entry::Vacant(VacantEntry {
+                key, path, link, len: &mut map.len, pool: &mut map.pool, marker: PhantomData,
+            })
+        }
+    }
+
+    /// Returns a reference to this entry's key.
+    pub fn key(&self) -> &T {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant.
+    /// Returns a mutable reference to the value in the entry.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// *map.entry("hits").or_insert(0) += 1;
+    /// *map.entry("hits").or_insert(0) += 1;
+    /// assert!(map["hits"] == 2);
+    /// ```
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    /// Inserts the result of `default` if the entry is vacant.
+    /// `default` is only called when a value is needed.
+    /// Returns a mutable reference to the value in the entry.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut V
+            where F: FnOnce() -> V {
+
+        match self {
+            Entry::Vacant(entry) => entry.insert(default()),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    /// Inserts the result of `default` if the entry is vacant.
+    /// `default` is passed a reference to the key, and is only
+    /// called when a value is needed.
+    /// Returns a mutable reference to the value in the entry.
+    pub fn or_insert_with_key<F>(self, default: F) -> &'a mut V
+            where F: FnOnce(&T) -> V {
+
+        match self {
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    /// Calls `func` with the existing value if the entry is occupied,
+    /// then returns the entry for further chaining.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// map.entry("ten").and_modify(|v| *v += 1).or_insert(10);
+    /// map.entry("ten").and_modify(|v| *v += 1).or_insert(10);
+    /// assert!(map["ten"] == 11);
+    /// ```
+    pub fn and_modify<F>(self, func: F) -> Self
+            where F: FnOnce(&mut V) {
+
+        match self {
+            Entry::Occupied(mut entry) => {
+                func(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, T, V: Default, A: Allocator + Clone> Entry<'a, T, V, A> {
+    /// Inserts `V::default()` if the entry is vacant.
+    /// Returns a mutable reference to the value in the entry.
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, T, V, A: Allocator + Clone> VacantEntry<'a, T, V, A> {
+    /// Returns a reference to the key that would be used
+    /// when inserting through this entry.
+    pub fn key(&self) -> &T {
+        &self.key
+    }
+
+    /// Takes back ownership of the key.
+    pub fn into_key(self) -> T {
+        self.key
+    }
+
+    // Makes sure inserting through this entry won't allocate,
+    // reporting allocation failure instead of aborting.
+    pub(crate) fn try_reserve(&mut self) -> Result<(), TryReserveError> {
+        self.pool.try_reserve_one()
+    }
+
+    /// Inserts `value` under this entry's key.
+    /// Returns a mutable reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        // Safety: link and path were recorded by Entry::new,
+        // and the map has been borrowed ever since.
+        unsafe {
+            *self.link = Some(self.pool.alloc(Node::new(self.key, value)));
+            let link = self.path.retrace_end();
+            *self.len += 1;
+            &mut (*link).as_mut().unwrap().value
+        }
+    }
+}
+
+impl<'a, T, V, A: Allocator + Clone> OccupiedEntry<'a, T, V, A> {
+    // Entry for the first or last Node in map, if there is one.
+    pub(crate) fn from_position<C>(map: &'a mut BstMap<T, V, C, A>, pos: NodePosition) -> Option<Self> {
+        let (path, link) = Node::seek_position(&mut map.head, pos);
+        // Safety: see Entry::new.
+        if unsafe { (*link).is_none() } { return None }
+        Some(OccupiedEntry { path, link, len: &mut map.len, pool: &mut map.pool, marker: PhantomData })
+    }
+
+    // The Node this entry refers to.
+    fn node(&self) -> &Node<T, V, A> {
+        // Safety: link holds a Node for as long as the entry exists.
+        unsafe { (*self.link).as_ref().unwrap() }
+    }
+
+    fn node_mut(&mut self) -> &mut Node<T, V, A> {
+        // Safety: see node.
+        unsafe { (*self.link).as_mut().unwrap() }
+    }
+
+    /// Returns a reference to this entry's key.
+    pub fn key(&self) -> &T {
+        &self.node().key
+    }
+
+    /// Returns a reference to this entry's value.
+    pub fn get(&self) -> &V {
+        &self.node().value
+    }
+
+    /// Returns a mutable reference to this entry's value.
+    /// Use `into_mut` for a reference that outlives the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.node_mut().value
+    }
+
+    /// Converts the entry into a mutable reference to its value,
+    /// bound to the lifetime of the map.
+    pub fn into_mut(self) -> &'a mut V {
+        // Safety: see node. The map stays borrowed for 'a.
+        unsafe { &mut (*self.link).as_mut().unwrap().value }
+    }
+
+    /// Replaces this entry's value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Removes this entry from the map.
+    /// Returns the key/value pair that was stored.
+    ///
+    /// ```
+    /// # use bstmap::{BstMap, Entry};
+    /// let mut map = BstMap::new();
+    /// map.insert("ten", 10);
+    /// if let Entry::Occupied(entry) = map.entry("ten") {
+    ///     assert!(entry.remove_entry() == ("ten", 10));
+    /// }
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn remove_entry(self) -> (T, V) {
+        // Safety: link holds a Node and path leads to it.
+        let node = unsafe { Node::unlink(self.path, self.link) };
+        *self.len -= 1;
+        self.pool.recycle(node, *self.len)
+    }
+
+    /// Removes this entry from the map, returning its value.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+}
+
+// trait impl
+
+impl<T: Debug, V: Debug, A: Allocator + Clone> Debug for Entry<'_, T, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Vacant(entry) => f.debug_tuple("Entry").field(entry).finish(),
+            Entry::Occupied(entry) => f.debug_tuple("Entry").field(entry).finish(),
+        }
+    }
+}
+
+impl<T: Debug, V: Debug, A: Allocator + Clone> Debug for VacantEntry<'_, T, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(self.key()).finish()
+    }
+}
+
+impl<T: Debug, V: Debug, A: Allocator + Clone> Debug for OccupiedEntry<'_, T, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<T: Debug, V: Debug, A: Allocator + Clone> Debug for OccupiedError<'_, T, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("key", self.entry.key())
+            .field("old_value", self.entry.get())
+            .field("new_value", &self.value)
+            .finish()
+    }
+}
+
+impl<T: Debug, V: Debug, A: Allocator + Clone> Display for OccupiedError<'_, T, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "failed to insert {:?}, key {:?} already exists with value {:?}",
+               self.value,
+               self.entry.key(),
+               self.entry.get())
+    }
+}
+
+impl<T: Debug, V: Debug, A: Allocator + Clone> Error for OccupiedError<'_, T, V, A> {}
@@ -1,112 +1,415 @@
-use std::fmt::Debug;
+use std::{iter::FusedIterator, marker::PhantomData, ptr::{self, NonNull}};
+use std::collections::VecDeque;
 use crate::BstMap;
+use crate::allocator::{Allocator, Global, NodeBox};
+use crate::compare::Natural;
+use crate::node::{Node, NodeLink};
 
 // Iterator implementations for BstMap.
-// All iterators are constructed by filling a vector with key/value 
-// pairs from the map. 
+// All iterators walk the tree lazily in order, keeping one stack of
+// Nodes for each end. A stack only ever holds a single spine of the
+// tree, so creating an iterator costs O(height), and each step costs
+// O(1) amortized.
 //
-// All three flavors of iterator can be constructed, including destructive. 
+// Both ends count down a shared len, which is how they know to stop
+// when they meet in the middle.
+//
+// All three flavors of iterator can be constructed, including destructive.
+
+/// Owning `Iterator` over the contents of a `BstMap`,  
+/// yielding key/value pairs in ascending key order.
+//
+// Takes the tree apart as it goes. The front stack holds Nodes whose
+// left subtree has been detached, the back stack Nodes whose right
+// subtree has been detached. Whenever one end runs dry it picks up
+// the rest of the tree from the bottom of the other end's stack.
+pub struct Iter<T, V, A: Allocator + Clone = Global> {
+    front: VecDeque<NodeBox<T, V, A>>,
+    back: VecDeque<NodeBox<T, V, A>>,
+    len: usize,
+}
+
+// Pushes link and its chain of left children onto stack,
+// detaching each left child as it goes.
+fn push_left_owned<T, V, A>(stack: &mut VecDeque<NodeBox<T, V, A>>, mut link: NodeLink<T, V, A>)
+        where A: Allocator + Clone {
+
+    while let Some(mut node) = link {
+        link = node.left.take();
+        stack.push_back(node);
+    }
+}
 
-// Owned Iterator
-pub struct Iter<T: Ord + Debug, V: Debug> {
-    pairs: Vec<(T, V)>,
+// Pushes link and its chain of right children onto stack,
+// detaching each right child as it goes.
+fn push_right_owned<T, V, A>(stack: &mut VecDeque<NodeBox<T, V, A>>, mut link: NodeLink<T, V, A>)
+        where A: Allocator + Clone {
+
+    while let Some(mut node) = link {
+        link = node.right.take();
+        stack.push_back(node);
+    }
 }
 
-impl<T: Ord + Debug, V: Debug> Iterator for Iter<T, V> {
+impl<T, V, A: Allocator + Clone> Iter<T, V, A> {
+    // Iterator taking apart the len Nodes under head.
+    pub(crate) fn new(head: NodeLink<T, V, A>, len: usize) -> Self {
+        // The front end starts out owning the whole tree.
+        let mut front = VecDeque::new();
+        push_left_owned(&mut front, head);
+
+        Iter {
+            front,
+            back: VecDeque::new(),
+            len,
+        }
+    }
+}
+
+impl<T, V, A: Allocator + Clone> Iterator for Iter<T, V, A> {
     type Item = (T, V);
+
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pairs.len() == 0 { None } 
-        else {
-            let pair = self.pairs.swap_remove(0);
-            Some(pair)
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        // The smallest remaining pair is in the left subtree of the
+        // oldest Node on the back stack, or is that Node itself.
+        if self.front.is_empty() {
+            let bottom = self.back.front_mut().unwrap();
+            match bottom.left.take() {
+                Some(left) => push_left_owned(&mut self.front, Some(left)),
+                None => {
+                    let node = self.back.pop_front().unwrap();
+                    return Some((node.key, node.value));
+                }
+            }
         }
+
+        let mut node = self.front.pop_back().unwrap();
+        push_left_owned(&mut self.front, node.right.take());
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
     }
 }
 
-// Reference Iterator
-pub struct IterRef<'a, T: Ord + Debug, V: Debug> {
-    pairs: Vec<(&'a T, &'a V)>,
-    index: usize,
+impl<T, V, A: Allocator + Clone> DoubleEndedIterator for Iter<T, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        // Mirror image of next.
+        if self.back.is_empty() {
+            let bottom = self.front.front_mut().unwrap();
+            match bottom.right.take() {
+                Some(right) => push_right_owned(&mut self.back, Some(right)),
+                None => {
+                    let node = self.front.pop_front().unwrap();
+                    return Some((node.key, node.value));
+                }
+            }
+        }
+
+        let mut node = self.back.pop_back().unwrap();
+        push_right_owned(&mut self.back, node.left.take());
+        Some((node.key, node.value))
+    }
+}
+
+impl<T, V, A: Allocator + Clone> ExactSizeIterator for Iter<T, V, A> {}
+impl<T, V, A: Allocator + Clone> FusedIterator for Iter<T, V, A> {}
+
+impl<T, V, A: Allocator + Clone> Drop for Iter<T, V, A> {
+    // Whatever wasn't yielded still has subtrees attached.
+    fn drop(&mut self) {
+        for node in self.front.drain(..).chain(self.back.drain(..)) {
+            Node::drop_tree(Some(node));
+        }
+    }
+}
+
+/// `Iterator` over references to the contents of a `BstMap`,  
+/// yielding key/value pairs in ascending key order.
+pub struct IterRef<'a, T, V, A: Allocator + Clone = Global> {
+    front: Vec<&'a Node<T, V, A>>,
+    back: Vec<&'a Node<T, V, A>>,
     len: usize,
 }
 
-impl<'a, T: Ord + Debug, V: Debug> Iterator for IterRef<'a, T, V> {
+// Pushes node and its chain of left children onto stack.
+pub fn push_left<'a, T, V, A>(stack: &mut Vec<&'a Node<T, V, A>>, mut node: Option<&'a Node<T, V, A>>)
+        where A: Allocator + Clone {
+
+    while let Some(next) = node {
+        stack.push(next);
+        node = next.left.as_deref();
+    }
+}
+
+// Pushes node and its chain of right children onto stack.
+pub fn push_right<'a, T, V, A>(stack: &mut Vec<&'a Node<T, V, A>>, mut node: Option<&'a Node<T, V, A>>)
+        where A: Allocator + Clone {
+
+    while let Some(next) = node {
+        stack.push(next);
+        node = next.right.as_deref();
+    }
+}
+
+impl<'a, T, V, A: Allocator + Clone> IterRef<'a, T, V, A> {
+    // Iterator over every pair from index onwards.
+    pub(crate) fn from_index(head: &'a NodeLink<T, V, A>, len: usize, index: usize) -> Self {
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+        push_right(&mut back, head.as_deref());
+
+        // Like push_left, except Nodes before index are skipped
+        // along with their left subtrees.
+        let mut skip = index;
+        let mut node = head.as_deref();
+        while let Some(next) = node {
+            let left_size = Node::link_size(&next.left);
+            if skip <= left_size {
+                front.push(next);
+                if skip == left_size { break }
+                node = next.left.as_deref();
+            } else {
+                skip -= left_size + 1;
+                node = next.right.as_deref();
+            }
+        }
+
+        IterRef {
+            front,
+            back,
+            len: len.saturating_sub(index),
+        }
+    }
+}
+
+impl<'a, T, V, A: Allocator + Clone> Iterator for IterRef<'a, T, V, A> {
     type Item = (&'a T, &'a V);
+
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index == self.len { None } 
-        else {
-            let pair = self.pairs[self.index];
-            self.index += 1;
-            Some(pair)
-        }
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        let node = self.front.pop().unwrap();
+        push_left(&mut self.front, node.right.as_deref());
+        Some((&node.key, &node.value))
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T, V, A: Allocator + Clone> DoubleEndedIterator for IterRef<'_, T, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        let node = self.back.pop().unwrap();
+        push_right(&mut self.back, node.left.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<T, V, A: Allocator + Clone> ExactSizeIterator for IterRef<'_, T, V, A> {}
+impl<T, V, A: Allocator + Clone> FusedIterator for IterRef<'_, T, V, A> {}
+
+/// `Iterator` over the contents of a `BstMap` with mutable values,  
+/// yielding key/value pairs in ascending key order.
+//
+// Both stacks can hold the same Node, so they hold raw pointers instead
+// of references. Once a value has been handed out, its Node is only ever
+// touched again through its left and right fields, never as a whole,
+// so the &mut V given out is never aliased.
+pub struct IterMut<'a, T, V, A: Allocator + Clone = Global> {
+    front: Vec<NonNull<Node<T, V, A>>>,
+    back: Vec<NonNull<Node<T, V, A>>>,
+    len: usize,
+    marker: PhantomData<&'a mut Node<T, V, A>>,
 }
 
-// Mutable Iterator
-pub struct IterMut<'a, T: Ord + Debug, V: Debug> {
-    pairs: Vec::<(&'a T, &'a mut V)>,
+// Pushes the Node held by link and its chain of left children onto stack.
+//
+// Safety: link must point into a tree borrowed mutably for 'a.
+pub unsafe fn push_left_mut<T, V, A>(stack: &mut Vec<NonNull<Node<T, V, A>>>, mut link: *mut NodeLink<T, V, A>)
+        where A: Allocator + Clone {
+
+    while let Some(node) = &mut *link {
+        let node = NonNull::new_unchecked(ptr::addr_of_mut!(**node));
+        stack.push(node);
+        link = ptr::addr_of_mut!((*node.as_ptr()).left);
+    }
 }
 
-impl<'a, T: Ord + Debug, V: Debug> Iterator for IterMut<'a, T, V> {
+// Pushes the Node held by link and its chain of right children onto stack.
+//
+// Safety: see push_left_mut.
+pub unsafe fn push_right_mut<T, V, A>(stack: &mut Vec<NonNull<Node<T, V, A>>>, mut link: *mut NodeLink<T, V, A>)
+        where A: Allocator + Clone {
+
+    while let Some(node) = &mut *link {
+        let node = NonNull::new_unchecked(ptr::addr_of_mut!(**node));
+        stack.push(node);
+        link = ptr::addr_of_mut!((*node.as_ptr()).right);
+    }
+}
+
+impl<'a, T, V, A: Allocator + Clone> Iterator for IterMut<'a, T, V, A> {
     type Item = (&'a T, &'a mut V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pairs.len() == 0 { None }
-        else {
-            let pair = self.pairs.swap_remove(0);
-            Some(pair)
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        // Safety: len guarantees this Node hasn't been yielded by either
+        // end, and the map stays mutably borrowed for 'a.
+        unsafe {
+            let node = self.front.pop().unwrap().as_ptr();
+            push_left_mut(&mut self.front, ptr::addr_of_mut!((*node).right));
+            Some((&(*node).key, &mut (*node).value))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T, V, A: Allocator + Clone> DoubleEndedIterator for IterMut<'_, T, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        // Safety: see next.
+        unsafe {
+            let node = self.back.pop().unwrap().as_ptr();
+            push_right_mut(&mut self.back, ptr::addr_of_mut!((*node).left));
+            Some((&(*node).key, &mut (*node).value))
         }
     }
 }
 
-// IntoIterator impl for three states of BstMap
+impl<T, V, A: Allocator + Clone> ExactSizeIterator for IterMut<'_, T, V, A> {}
+impl<T, V, A: Allocator + Clone> FusedIterator for IterMut<'_, T, V, A> {}
+
+// IterMut hands out the same references as &'a mut BstMap would.
+unsafe impl<T: Sync, V: Send, A: Allocator + Clone> Send for IterMut<'_, T, V, A> {}
+unsafe impl<T: Sync, V: Sync, A: Allocator + Clone> Sync for IterMut<'_, T, V, A> {}
+
+/// `Iterator` removing the entries of a `BstMap` which match a predicate,
+/// yielding them as owned key/value pairs in ascending key order.
+///
+/// Constructed with `BstMap::extract_if`. Entries left unvisited when it is
+/// dropped stay in the map.
+//
+// Walks the map in place by index. A matching Node is unlinked right away,
+// which moves every later pair down one index, so the index only moves on
+// past pairs which are kept. Kept Nodes are left where they are, and if
+// pred panics the pair it was looking at is still in the map.
+pub struct ExtractIf<'a, T, V, F, C = Natural, A: Allocator + Clone = Global>
+        where F: FnMut(&T, &mut V) -> bool {
+
+    map: &'a mut BstMap<T, V, C, A>,
+    // Index of the next pair to visit.
+    index: usize,
+    pred: F,
+}
+
+impl<'a, T, V, F, C, A: Allocator + Clone> ExtractIf<'a, T, V, F, C, A>
+        where F: FnMut(&T, &mut V) -> bool {
+
+    pub(crate) fn new(map: &'a mut BstMap<T, V, C, A>, pred: F) -> Self {
+        ExtractIf { map, index: 0, pred }
+    }
+}
+
+impl<T, V, F, C, A: Allocator + Clone> Iterator for ExtractIf<'_, T, V, F, C, A>
+        where F: FnMut(&T, &mut V) -> bool {
 
-impl<T: Ord + Debug + Debug, V: Debug> IntoIterator for BstMap<T, V> {
     type Item = (T, V);
-    type IntoIter = Iter<T, V>;
 
-    fn into_iter(self) -> Self::IntoIter {
-        let mut pairs = Vec::new();
-        if self.head.is_some() {
-            self.head.unwrap().fill_owned_vec(&mut pairs);
+    fn next(&mut self) -> Option<Self::Item> {
+        let map = &mut *self.map;
+        while self.index < map.len {
+            let (path, link) = Node::seek_index(&mut map.head, self.index);
+            // Safety: path and link were just recorded from map.head,
+            // and link holds a Node since index is in bounds.
+            unsafe {
+                let node = (*link).as_mut().unwrap();
+                if (self.pred)(&node.key, &mut node.value) {
+                    let node = Node::unlink(path, link);
+                    map.len -= 1;
+                    return Some(map.pool.recycle(node, map.len));
+                }
+            }
+            self.index += 1;
         }
+        None
+    }
 
-        Iter {
-            pairs,
-        }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.map.len - self.index))
     }
 }
 
-impl<'a, T: Ord + Debug + Debug, V: Debug> IntoIterator for &'a BstMap<T, V> {
+impl<T, V, F, C, A: Allocator + Clone> FusedIterator for ExtractIf<'_, T, V, F, C, A>
+        where F: FnMut(&T, &mut V) -> bool {}
+
+// IntoIterator impl for three states of BstMap.
+// Ordering is guaranteed: all three yield pairs in ascending key order.
+
+impl<T, V, C, A: Allocator + Clone> IntoIterator for BstMap<T, V, C, A> {
+    type Item = (T, V);
+    type IntoIter = Iter<T, V, A>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        Iter::new(self.head.take(), self.len)
+    }
+}
+
+impl<'a, T, V, C, A: Allocator + Clone> IntoIterator for &'a BstMap<T, V, C, A> {
     type Item = (&'a T, &'a V);
-    type IntoIter = IterRef<'a, T, V>;
+    type IntoIter = IterRef<'a, T, V, A>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let mut pairs = Vec::new();
-        if self.head.is_some() {
-            self.head.as_ref().unwrap().fill_ref_vec(&mut pairs);
-        }
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+        push_left(&mut front, self.head.as_deref());
+        push_right(&mut back, self.head.as_deref());
 
         IterRef {
-            pairs,
-            index: 0,
+            front,
+            back,
             len: self.len,
         }
     }
 }
 
-impl<'a, T: Ord + Debug + Debug, V: Debug> IntoIterator for &'a mut BstMap<T, V> {
+impl<'a, T, V, C, A: Allocator + Clone> IntoIterator for &'a mut BstMap<T, V, C, A> {
     type Item = (&'a T, &'a mut V);
-    type IntoIter = IterMut<'a, T, V>;
+    type IntoIter = IterMut<'a, T, V, A>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let mut pairs = Vec::new();
-        if self.head.is_some() {
-            self.head.as_mut().unwrap().fill_mut_vec(&mut pairs);
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+        // Safety: the map is borrowed mutably for 'a.
+        unsafe {
+            push_left_mut(&mut front, &mut self.head);
+            push_right_mut(&mut back, &mut self.head);
         }
 
         IterMut {
-            pairs,
+            front,
+            back,
+            len: self.len,
+            marker: PhantomData,
         }
     }
 }
@@ -27,24 +27,71 @@
 //! assert!(removed == 11);
 //! assert!(map.is_empty());
 //! ```
-use std::{ops::Index, fmt::{Display, Debug}};
+//!
+//! # Features
+//!
+//! - `serde`: `Serialize` and `Deserialize` for `BstMap`.
+//! - `allocator_api`: lets `BstMap::new_in` box nodes in any
+//!   `std::alloc::Allocator`. Needs a nightly compiler.
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+use std::{ops::{Index, RangeBounds}, fmt::{Display, Debug}, borrow::Borrow};
+use std::{cmp::Ordering, hash::{Hash, Hasher}};
 
 mod iter;
 mod node;
 mod action;
+mod entry;
+mod range;
+mod render;
+mod dot;
+mod snapshot;
+mod arena;
+mod persistent;
+mod pool;
+mod allocator;
+mod compare;
+#[cfg(feature = "serde")]
+mod serde_impl;
 use iter::*;
 use node::*;
 use action::*;
+use pool::NodePool;
+use allocator::NodeBox;
+pub use entry::{Entry, OccupiedEntry, OccupiedError, VacantEntry};
+pub use range::{Range, RangeMut};
+pub use iter::ExtractIf;
+pub use render::{Renderer, Layout, Charset};
+pub use dot::DotOptions;
+pub use snapshot::{Encode, Decode, SnapshotError};
+pub use arena::{ArenaBstMap, ArenaIter};
+pub use persistent::{PersistentBstMap, PersistentIntoIter, PersistentIter};
+pub use pool::{TryReserveError, TryReserveErrorKind};
+pub use allocator::{Allocator, Global};
+pub use compare::{Compare, Natural, Reverse};
+#[cfg(feature = "serde")]
+pub use serde_impl::{DuplicateKeys, deserialize_unique};
 
 /// BstMap instance struct.  
 /// Short for "Binary Search Tree Map."
+///
+/// The tree rebalances itself (AVL) on every insert and remove,
+/// so lookups stay O(log n) regardless of insertion order.
+///
+/// Keys are ordered by the comparator `C`, their `Ord` implementation
+/// unless another one is given with `with_comparator`.
+///
+/// Every entry is a node boxed in the allocator `A`, see `new_in`.
 #[derive(Debug)]
-pub struct BstMap<T: Ord + Debug, V: Debug> {
+pub struct BstMap<T, V, C = Natural, A: Allocator + Clone = Global> {
     len: usize,
-    head: Option<Node<T, V>>,
+    head: NodeLink<T, V, A>,
+    // Spare Node allocations, see pool.rs.
+    pool: NodePool<T, V, A>,
+    // Decides which way every descent turns, see compare.rs.
+    cmp: C,
 }
 
-impl<T: Ord + Debug + Debug, V: Debug> BstMap<T, V> {
+impl<T: Ord, V> BstMap<T, V> {
     /// Creates an empty `BstMap`.
     ///
     /// ```
@@ -53,12 +100,120 @@ impl<T: Ord + Debug + Debug, V: Debug> BstMap<T, V> {
     /// assert!(map.is_empty()); // Yup, she's empty.
     /// ```
     pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+
+    /// Creates a `BstMap` out of pairs already in strictly ascending
+    /// key order, building a perfectly balanced tree in O(n).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key is not greater than the one before it.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let map = BstMap::from_sorted_iter((0..1000).map(|i| (i, i * 2)));
+    /// assert!(map.len() == 1000 && map[&500] == 1000);
+    /// ```
+    pub fn from_sorted_iter<I>(iter: I) -> Self
+            where I: IntoIterator<Item = (T, V)> {
+
+        let pairs: Vec<_> = iter.into_iter().collect();
+        assert!(pairs.windows(2).all(|pair| pair[0].0 < pair[1].0),
+                "keys passed to from_sorted_iter are not in strictly ascending order");
+        Self {
+            len: pairs.len(),
+            head: Node::from_sorted(pairs, &Global),
+            pool: NodePool::new(Global),
+            cmp: Natural,
+        }
+    }
+
+    /// Creates an empty `BstMap` with room for at least `capacity`
+    /// entries, so inserting them won't allocate.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let map = BstMap::<u32, u32>::with_capacity(100);
+    /// assert!(map.is_empty() && map.capacity() >= 100);
+    /// ```
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_in(capacity, Global)
+    }
+}
+
+impl<T, V, C> BstMap<T, V, C> {
+    /// Creates an empty `BstMap` which orders its keys with `cmp`
+    /// instead of their `Ord` implementation.
+    ///
+    /// `cmp` is used for every lookup as well, so looking up a borrowed
+    /// form `Q` of the key type needs `cmp` to implement `Compare<Q>`.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::with_comparator(|a: &String, b: &String| {
+    ///     a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    /// });
+    /// for word in ["ccc", "a", "bb", "aa"] {
+    ///     map.insert(word.to_string(), ());
+    /// }
+    /// let keys: Vec<_> = map.iter().map(|(k, _)| k.as_str()).collect();
+    /// assert!(keys == ["a", "aa", "bb", "ccc"]);
+    /// assert!(map.floor(&"b".to_string()).unwrap().0 == "a");
+    /// ```
+    pub fn with_comparator(cmp: C) -> Self {
+        Self::with_comparator_in(cmp, Global)
+    }
+}
+
+impl<T: Ord, V, A: Allocator + Clone> BstMap<T, V, Natural, A> {
+    /// Creates an empty `BstMap` which boxes its nodes in `alloc`.
+    ///
+    /// Allocators other than `Global` need the `allocator_api`
+    /// feature, and with it a nightly compiler.
+    ///
+    /// ```
+    /// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+    /// # use bstmap::{BstMap, Global};
+    /// let mut map = BstMap::new_in(Global);
+    /// map.insert(1, "a");
+    /// assert!(map.len() == 1);
+    /// ```
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_comparator_in(Natural, alloc)
+    }
+
+    /// Creates an empty `BstMap` which boxes its nodes in `alloc`,
+    /// with room for at least `capacity` entries.
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        let mut map = Self::new_in(alloc);
+        map.reserve(capacity);
+        map
+    }
+}
+
+impl<T, V, C, A: Allocator + Clone> BstMap<T, V, C, A> {
+    /// Creates an empty `BstMap` which orders its keys with `cmp`
+    /// and boxes its nodes in `alloc`.
+    pub fn with_comparator_in(cmp: C, alloc: A) -> Self {
         Self {
             len: 0,
             head: None,
+            pool: NodePool::new(alloc),
+            cmp,
         }
     }
 
+    /// Returns a reference to the allocator map boxes its nodes in.
+    pub fn allocator(&self) -> &A {
+        self.pool.allocator()
+    }
+
+    /// Returns a reference to the comparator map orders its keys with.
+    pub fn comparator(&self) -> &C {
+        &self.cmp
+    }
+
     /// Empties map contents.
     ///
     /// ```
@@ -71,7 +226,7 @@ impl<T: Ord + Debug + Debug, V: Debug> BstMap<T, V> {
     /// ```
     pub fn clear(&mut self) { 
         self.len = 0;
-        self.head = None;
+        Node::drop_tree(self.head.take());
     }
 
     /// Returns true if map is empty.
@@ -80,39 +235,212 @@ impl<T: Ord + Debug + Debug, V: Debug> BstMap<T, V> {
     /// Returns number of map entries.
     pub fn len(&self) -> usize { self.len }
 
+    /// Returns the number of entries map can hold without allocating.
+    ///
+    /// Every entry lives in its own heap allocated node. Reserving
+    /// capacity allocates spare nodes up front, and removing entries
+    /// keeps their nodes around as spares until the map is shrunk,
+    /// as long as it stays within the capacity reserved.
+    /// `drain` frees its nodes instead.
+    pub fn capacity(&self) -> usize { self.len + self.pool.spares() }
+
+    /// Reserves capacity for at least `additional` more entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new capacity overflows `usize`.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// map.insert(0, 0);
+    /// map.reserve(10);
+    /// assert!(map.capacity() >= 11);
+    /// ```
+    pub fn reserve(&mut self, additional: usize) {
+        self.pool.reserve(self.len, additional);
+    }
+
+    /// Tries to reserve capacity for at least `additional` more entries,
+    /// returning an error instead of aborting if allocation fails.
+    ///
+    /// ```
+    /// # use bstmap::{BstMap, TryReserveErrorKind};
+    /// let mut map = BstMap::<u8, u8>::new();
+    /// assert!(map.try_reserve(10).is_ok());
+    /// let err = map.try_reserve(usize::MAX).unwrap_err();
+    /// assert!(err.kind() == TryReserveErrorKind::CapacityOverflow);
+    /// ```
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.pool.try_reserve(self.len, additional)
+    }
+
+    /// Frees every spare node, so capacity drops to `len`.
+    pub fn shrink_to_fit(&mut self) {
+        self.pool.shrink();
+    }
+
     /// Returns `Iterator` over contents of map   
     /// in key/value tuples `(key: &'a T, value: &'a V)`.
     ///  
-    /// No guaranteed ordering.
-    pub fn iter(&self) -> IterRef<T, V> {
+    /// Pairs are always yielded in ascending key order.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// map.insert(3, "c");
+    /// map.insert(1, "a");
+    /// map.insert(2, "b");
+    /// let keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+    /// assert!(keys == [1, 2, 3]);
+    /// ```
+    pub fn iter(&self) -> IterRef<'_, T, V, A> {
         self.into_iter()
     }
 
     /// Returns mutable value `Iterator` over contents of map   
     /// in key/value tuples `(key: &'a T, value: &'a mut V)`.
     ///  
-    /// No guaranteed ordering.
-    pub fn iter_mut(&mut self) -> IterMut<T, V> {
+    /// Pairs are always yielded in ascending key order.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, V, A> {
         self.into_iter()
     }
+}
+
+impl<T, V, C: Compare<T>, A: Allocator + Clone> BstMap<T, V, C, A> {
+    /// Returns `Iterator` over the entries of map whose keys  
+    /// fall within `range`, in ascending key order.
+    ///
+    /// Subtrees outside of the range are never visited.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// for i in 0..10 {
+    ///     map.insert(i, i * 10);
+    /// }
+    /// let values: Vec<_> = map.range(3..6).map(|(_, v)| *v).collect();
+    /// assert!(values == [30, 40, 50]);
+    /// let keys: Vec<_> = map.range(..=2).rev().map(|(k, _)| *k).collect();
+    /// assert!(keys == [2, 1, 0]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if range `start > end`, or if range `start == end`  
+    /// and both bounds are `Excluded`.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, T, V, A>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q> {
+
+        Range::new(&self.head, range, &self.cmp)
+    }
+
+    /// Returns mutable value `Iterator` over the entries of map  
+    /// whose keys fall within `range`, in ascending key order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if range `start > end`, or if range `start == end`  
+    /// and both bounds are `Excluded`.
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, T, V, A>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q> {
+
+        RangeMut::new(&mut self.head, range, &self.cmp)
+    }
 
     /// Inserts a key/value pair into map.
-    /// If key exists, existing value is clobbered. 
-    pub fn insert(&mut self, key: T, value: V) {
-        match self.head {
-            Some(ref mut node) => {
-                // Check returned InsertAction to see if we
-                // need to increment len
-                if let InsertAction::Increment = node.insert(key, value) {
-                    self.len += 1;
-                }
+    /// If key exists, existing value is clobbered and returned
+    /// as `Some(value)`. The stored key is left as it was.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// assert!(map.insert("ten", 10).is_none());
+    /// assert!(map.insert("ten", 11) == Some(10));
+    /// ```
+    pub fn insert(&mut self, key: T, value: V) -> Option<V> {
+        let action = Node::insert(&mut self.head, &mut self.pool, key, value, &self.cmp);
+        self._handle_insert(action)
+    }
+
+    /// Inserts a key/value pair into map only if key is absent,
+    /// returning a mutable reference to the inserted value.
+    ///
+    /// If key exists, nothing is changed, and the returned error
+    /// holds both the existing entry and `value`.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// *map.try_insert("ten", 10).unwrap() += 1;
+    /// let err = map.try_insert("ten", 20).unwrap_err();
+    /// assert!(*err.entry.get() == 11 && err.value == 20);
+    /// ```
+    pub fn try_insert(&mut self, key: T, value: V) -> Result<&mut V, OccupiedError<'_, T, V, A>> {
+        match self.entry(key) {
+            Entry::Vacant(entry) => Ok(entry.insert(value)),
+            Entry::Occupied(entry) => Err(OccupiedError { entry, value }),
+        }
+    }
+
+    /// Like `insert`, but returns an error instead of aborting if
+    /// a node for the new entry can't be allocated, in which case
+    /// map is left unchanged.
+    ///
+    /// Never allocates if there is spare capacity.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::with_capacity(1);
+    /// assert!(map.insert_fallible("ten", 10) == Ok(None));
+    /// assert!(map.insert_fallible("ten", 11) == Ok(Some(10)));
+    /// ```
+    pub fn insert_fallible(&mut self, key: T, value: V) -> Result<Option<V>, TryReserveError> {
+        // Only a vacant entry needs a node.
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Ok(Some(entry.insert(value))),
+            Entry::Vacant(mut entry) => {
+                entry.try_reserve()?;
+                entry.insert(value);
+                Ok(None)
             }
-            // First node! 
-            None => {
-                self.head = Some(Node::new(key, value));
-                self.len += 1;
+        }
+    }
+
+    /// Like `insert_or`, but returns an error instead of aborting if
+    /// a node for the new entry can't be allocated, in which case
+    /// map is left unchanged.
+    pub fn insert_or_fallible<F>(&mut self, key: T, value: V, mut func: F) -> Result<(), TryReserveError>
+            where F: FnMut(&mut V) {
+
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => func(entry.get_mut()),
+            Entry::Vacant(mut entry) => {
+                entry.try_reserve()?;
+                entry.insert(value);
             }
         }
+        Ok(())
+    }
+
+    /// Swaps the key stored in map for `key`, which is equal to it,
+    /// returning the key that was stored, or `None` if there was no
+    /// equal key. Useful when equal keys can still be told apart,
+    /// like interned strings.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let stored = String::from("ten");
+    /// let mut map = BstMap::new();
+    /// map.insert(stored.as_str(), 10);
+    /// let replacement = "ten";
+    /// let old = map.replace_key(replacement).unwrap();
+    /// assert!(old.as_ptr() == stored.as_ptr());
+    /// assert!(map.first_key_value().unwrap().0.as_ptr() == replacement.as_ptr());
+    /// assert!(map.replace_key("eleven").is_none());
+    /// ```
+    pub fn replace_key(&mut self, key: T) -> Option<T> {
+        Node::replace_key(&mut self.head, key, &self.cmp)
     }
 
     /// Inserts a key/value pair into map, and  
@@ -128,41 +456,161 @@ impl<T: Ord + Debug + Debug, V: Debug> BstMap<T, V> {
     /// // Attempt to insert another entry with same key, new value.
     /// map.insert_or(10, 20, |v| { *v += 1; });
     /// // Value was updated to 11 inside of closure instead of 20.
-    /// assert!(*map.get(10).unwrap() == 11); // Pass!
+    /// assert!(*map.get(&10).unwrap() == 11); // Pass!
     /// ```
     pub fn insert_or<F>(&mut self, key: T, value: V, func: F) 
             where F: FnMut(&mut V) {
 
-        match self.head {
-            Some(ref mut node) => {
-                // Check returned InsertAction to see if we
-                // need to increment len
-                if let InsertAction::Increment = node.insert_or(key, value, func) {
-                    self.len += 1;
+        let action = Node::insert_or(&mut self.head, &mut self.pool, key, value, &self.cmp, func);
+        self._handle_insert(action);
+    }
+
+    /// Gets the entry for key for in-place manipulation.  
+    /// Only descends the tree once, however the entry is then used.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map: BstMap<&str, u32> = BstMap::new();
+    /// for word in ["a", "b", "a"] {
+    ///     *map.entry(word).or_default() += 1;
+    /// }
+    /// assert!(map["a"] == 2 && map["b"] == 1);
+    /// ```
+    pub fn entry(&mut self, key: T) -> Entry<'_, T, V, A> {
+        Entry::new(self, key)
+    }
+
+    /// Splits map in two at `key`. Everything from `key` onwards  
+    /// is moved into the returned map, everything before it stays.  
+    /// Runs in O(log n).
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// for i in 0..10 {
+    ///     map.insert(i, ());
+    /// }
+    /// let high = map.split_off(&7);
+    /// assert!(map.len() == 7 && high.len() == 3);
+    /// assert!(high.first_key_value() == Some((&7, &())));
+    /// ```
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+            where T: Borrow<Q>, C: Compare<Q> + Clone, Q: ?Sized {
+
+        let (left, found, right) = Node::split(self.head.take(), key, &self.cmp);
+        // key itself belongs on the right.
+        let right = match found {
+            Some(node) => Some(Node::join(None, node, right)),
+            None => right,
+        };
+
+        // Subtree sizes make recounting both sides free.
+        self.head = left;
+        self.len = Node::link_size(&self.head);
+        Self {
+            len: Node::link_size(&right),
+            head: right,
+            pool: NodePool::new(self.allocator().clone()),
+            cmp: self.cmp.clone(),
+        }
+    }
+
+    /// Moves every entry out of `other` and into map, leaving `other`  
+    /// empty. If a key exists in both, the value from `other` wins.
+    ///
+    /// If all of `other`'s keys sort entirely before or after this map's,  
+    /// the two trees are joined in O(log n). Otherwise only the entries  
+    /// within the overlapping key range are merged one by one.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// let mut other = BstMap::new();
+    /// map.insert(1, "a");
+    /// other.insert(2, "b");
+    /// map.append(&mut other);
+    /// assert!(map.len() == 2 && other.is_empty());
+    /// ```
+    pub fn append(&mut self, other: &mut Self) {
+        if other.is_empty() { return }
+        if self.is_empty() {
+            std::mem::swap(self, other);
+            return
+        }
+
+        let mut other_head = other.head.take();
+        other.len = 0;
+        let (other_first, _) = other_head.as_ref().unwrap().first_key_value().unwrap();
+        let (other_last, _) = other_head.as_ref().unwrap().last_key_value().unwrap();
+        let after = self.cmp.compare(self.last_key_value().unwrap().0, other_first) == Ordering::Less;
+        let before = self.cmp.compare(other_last, self.first_key_value().unwrap().0) == Ordering::Less;
+
+        // Other fits entirely after us
+        if after {
+            self.head = Node::join2(self.head.take(), other_head);
+        }
+        // Other fits entirely before us
+        else if before {
+            self.head = Node::join2(other_head, self.head.take());
+        }
+        // Key ranges overlap. Cut out the part of us that overlaps
+        // with other and merge only that, then join everything back up.
+        else {
+            let (low, _, rest) = Node::split(self.head.take(), other_first, &self.cmp);
+            let (mut middle, _, high) = Node::split(rest, other_last, &self.cmp);
+            // The Nodes matching other's first and last key are dropped,
+            // since other's values win anyway.
+
+            // Insert whichever side is smaller into the other one.
+            let merged = if Node::link_size(&middle) < Node::link_size(&other_head) {
+                let len = Node::link_size(&middle);
+                for (key, value) in Iter::new(middle, len) {
+                    Node::insert_or(&mut other_head, &mut self.pool, key, value, &self.cmp, |_| ());
                 }
-            }
-            // First node! 
-            None => {
-                self.head = Some(Node::new(key, value));
-                self.len += 1;
-            }
+                other_head
+            } else {
+                let len = Node::link_size(&other_head);
+                for (key, value) in Iter::new(other_head, len) {
+                    Node::insert(&mut middle, &mut self.pool, key, value, &self.cmp);
+                }
+                middle
+            };
+            self.head = Node::join2(Node::join2(low, merged), high);
         }
+
+        self.len = Node::link_size(&self.head);
     }
 
     /// Returns `Some(&value)` associated with key,  
     /// or `None` if key wasn't found. 
-    pub fn get(&self, key: T) -> Option<&V> {
+    ///
+    /// The key may be any borrowed form of the map's key type,
+    /// so a `BstMap<String, V>` can be searched with a `&str`.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// map.insert(String::from("ten"), 10);
+    /// assert!(map.get("ten") == Some(&10));
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
         if let Some(node) = &self.head {
-            node.get(key) 
+            node.get(key, &self.cmp) 
         } 
         else { None }
     }
 
     /// Returns `Some(&mut value)` associated with key,  
     /// or `None` if key wasn't found. 
-    pub fn get_mut(&mut self, key: T) -> Option<&mut V> {
+    ///
+    /// The key may be any borrowed form of the map's key type.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
         if let Some(ref mut node) = self.head {
-            node.get_mut(key) 
+            node.get_mut(key, &self.cmp) 
         } 
         else { None }
     }
@@ -183,162 +631,681 @@ impl<T: Ord + Debug + Debug, V: Debug> BstMap<T, V> {
         else { None }
     }
 
-    /// Removes entry and returns the `Some(value)` associated  
-    /// with key.  
-    /// Returns `None` if key wasn't found.
-    pub fn remove(&mut self, key: T) -> Option<V> {
-        if let Some(ref mut node) = self.head {
-            // Check what action we should take with return value
-            // from remove call. 
-            match node.remove(key) {
-                // Just a return value which may be Some or None
-                RemoveAction::Return(value) => {
-                    // If the value actually contains Some,
-                    // decrement our len because a node was
-                    // removed
-                    if value.is_some() { self.len -= 1 }
-                    value
-                }
-                // A call to update a child node which means
-                // our head was the removed node. Update head with
-                // passed node. 
-                RemoveAction::UpdateNode(node) => {
-                    self.len -= 1;
-                    let old_head = self.head.take().unwrap();
-                    self.head = {
-                        // The node isn't boxed at the top level, so
-                        // we strip the box
-                        if let Some(node) = node {
-                            Some(*node)
-                        // Otherwise the last node was removed and
-                        // the head should now point to None. 
-                        } else { None }
-                    };
-                    Some(old_head.value)
-                }
-            }
+    /// Returns the key/value pair with the largest key less than or equal to `key`,  
+    /// or `None` if there is no such key.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// for key in [10, 20, 30] {
+    ///     map.insert(key, key / 10);
+    /// }
+    /// assert!(map.floor(&25) == Some((&20, &2)));
+    /// assert!(map.floor(&20) == Some((&20, &2)));
+    /// assert!(map.lower(&20) == Some((&10, &1)));
+    /// assert!(map.ceiling(&25) == Some((&30, &3)));
+    /// assert!(map.higher(&30).is_none());
+    /// ```
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        if let Some(node) = &self.head {
+            node.neighbor(key, Neighbor::Floor, &self.cmp)
         } 
         else { None }
     }
 
-    pub fn remove_first(&mut self) -> Option<V> {
-        self._remove_position(NodePosition::First)
+    /// Returns the key/value pair with the smallest key greater than or equal to `key`,  
+    /// or `None` if there is no such key.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        if let Some(node) = &self.head {
+            node.neighbor(key, Neighbor::Ceiling, &self.cmp)
+        } 
+        else { None }
     }
 
-    pub fn remove_last(&mut self) -> Option<V> {
-        self._remove_position(NodePosition::Last)
+    /// Returns the key/value pair with the largest key strictly less than `key`,  
+    /// or `None` if there is no such key.
+    pub fn lower<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        if let Some(node) = &self.head {
+            node.neighbor(key, Neighbor::Lower, &self.cmp)
+        } 
+        else { None }
     }
 
-    fn _remove_position(&mut self, position: NodePosition) -> Option<V> {
-        if let Some(ref mut node) = self.head {
-            // As long as we have a head, some node is going to get
-            // removed in this process, so we can decrement now.
-            self.len -= 1;
-            match node.remove_position(position) {
-                // We know value is Some because as long
-                // as the list has a head node, something is
-                // going to be returned, and a node muset have
-                // been removed.
-                RemoveAction::Return(value) => value,
-                RemoveAction::UpdateNode(node) => {
-                    let old_head = self.head.take().unwrap();
-                    self.head = {
-                        // The node isn't boxed at the top level, so
-                        // we strip the box
-                        if let Some(node) = node {
-                            Some(*node)
-                        // Otherwise the last node was removed and
-                        // the head should now point to None. 
-                        } else { None }
-                    };
-                    Some(old_head.value)
-                }
-            }
+    /// Returns the key/value pair with the smallest key strictly greater than `key`,  
+    /// or `None` if there is no such key.
+    pub fn higher<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        if let Some(node) = &self.head {
+            node.neighbor(key, Neighbor::Higher, &self.cmp)
         } 
-        // Can't remove anything if the three doesn't even have a head.
         else { None }
     }
-}
 
-// Trait Impls
-impl<T: Ord + Debug + Debug, V: Debug> Index<T> for BstMap<T, V> {
-    type Output = V;
+    /// Same as `floor`, with a mutable value.
+    pub fn floor_mut<Q>(&mut self, key: &Q) -> Option<(&T, &mut V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
 
-    fn index(&self, key: T) -> &Self::Output {
-        self.get(key).expect("no entry found for key")
+        if let Some(ref mut node) = self.head {
+            node.neighbor_mut(key, Neighbor::Floor, &self.cmp)
+        } 
+        else { None }
     }
-}
 
-impl<T: Ord + Debug, V: Debug> Display for BstMap<T, V> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let node_display = match &self.head {
-            Some(node) => format!("{}", node),
-            None => String::new()
-        };
-        write!(f,
-               "[BstMap @ {:p}]\
-              \n      len: {}\
-              \n head key: {:?}{}",
-               self,               
-               self.len, 
-               self.head.as_ref().unwrap().key,
-               node_display)
+    /// Same as `ceiling`, with a mutable value.
+    pub fn ceiling_mut<Q>(&mut self, key: &Q) -> Option<(&T, &mut V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        if let Some(ref mut node) = self.head {
+            node.neighbor_mut(key, Neighbor::Ceiling, &self.cmp)
+        } 
+        else { None }
     }
-}
 
+    /// Same as `lower`, with a mutable value.
+    pub fn lower_mut<Q>(&mut self, key: &Q) -> Option<(&T, &mut V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
 
-#[cfg(test)]
-mod tests {
-    #[allow(unused_imports)]
-    use super::*;
+        if let Some(ref mut node) = self.head {
+            node.neighbor_mut(key, Neighbor::Lower, &self.cmp)
+        } 
+        else { None }
+    }
 
-    #[test]
-    fn insert_and_get() {
-        let mut map = BstMap::new();
-        map.insert(0, 0);
-        assert!(*map.get(0).unwrap() == 0);
-        map.insert(1, 1);
-        assert!(*map.get(1).unwrap() == 1);
-        map.insert(1, 2);
-        assert!(*map.get(1).unwrap() == 2);
+    /// Same as `higher`, with a mutable value.
+    pub fn higher_mut<Q>(&mut self, key: &Q) -> Option<(&T, &mut V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        if let Some(ref mut node) = self.head {
+            node.neighbor_mut(key, Neighbor::Higher, &self.cmp)
+        } 
+        else { None }
     }
 
-    #[test]
-    fn insert_or() {
-        let mut map = BstMap::new();
-        map.insert(0, 0);
-        assert!(*map.get(0).unwrap() == 0);
-        map.insert_or(0, 10, |v| { *v += 1; });
-        assert!(*map.get(0).unwrap() == 1);
+    /// Returns the key/value pair at `index` in sorted order,  
+    /// counting from 0, or `None` if `index >= len`.  
+    /// Runs in O(log n).
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// for key in [50, 10, 40, 20, 30] {
+    ///     map.insert(key, ());
+    /// }
+    /// assert!(map.get_index(0) == Some((&10, &())));
+    /// assert!(map.get_index(3) == Some((&40, &())));
+    /// assert!(map.get_index(5).is_none());
+    /// ```
+    pub fn get_index(&self, index: usize) -> Option<(&T, &V)> {
+        if let Some(node) = &self.head {
+            node.get_index(index)
+        } 
+        else { None }
     }
 
-    #[test]
-    fn clear() {
-        let mut map = BstMap::new();
-        map.insert(0, 0);
-        map.insert(1, 0);
-        map.insert(2, 0);
-        map.insert(3, 0);
-        map.insert(4, 0);
-        map.insert(5, 0);
-        map.insert(6, 0);
-        map.insert(7, 0);
-        assert!(!map.is_empty());
+    /// Returns the number of keys in map strictly less than `key`.  
+    /// If `key` is in map, this is its index in sorted order.  
+    /// Runs in O(log n).
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// for key in [50, 10, 40, 20, 30] {
+    ///     map.insert(key, ());
+    /// }
+    /// assert!(map.rank(&30) == 2);
+    /// assert!(map.rank(&35) == 3);
+    /// assert!(map.rank(&99) == 5);
+    /// ```
+    pub fn rank<Q>(&self, key: &Q) -> usize
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        if let Some(node) = &self.head {
+            node.rank(key, &self.cmp)
+        } 
+        else { 0 }
     }
 
-    #[test]
-    fn get_mut() {
-        let mut map = BstMap::new();
-        map.insert(0, 0);
-        let val = map.get_mut(0).unwrap();
-        *val += 1;
-        assert!(*map.get(0).unwrap() == 1);
+    /// Returns `Iterator` over contents of map starting at `index`  
+    /// in sorted order, in key/value tuples `(key: &'a T, value: &'a V)`.  
+    /// Finding the start runs in O(log n).
+    pub fn iter_from_index(&self, index: usize) -> IterRef<'_, T, V, A> {
+        IterRef::from_index(&self.head, self.len, index)
     }
 
-    #[test]
-    fn first_last_key_value() {
-        let mut map = BstMap::new();
-        map.insert(2, 2);
+    /// Removes entry and returns the `Some(value)` associated  
+    /// with key.  
+    /// Returns `None` if key wasn't found.
+    ///
+    /// The key may be any borrowed form of the map's key type.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let action = Node::remove(&mut self.head, key, &self.cmp);
+        self._handle_remove(action).map(|(_, value)| value)
+    }
+
+    /// Removes the entry at `index` in sorted order and returns  
+    /// `Some((key, value))`, or `None` if `index >= len`.  
+    /// Runs in O(log n).
+    pub fn remove_index(&mut self, index: usize) -> Option<(T, V)> {
+        let action = Node::remove_index(&mut self.head, index);
+        self._handle_remove(action)
+    }
+
+    /// Removes the entry with the smallest key and returns  
+    /// `Some((key, value))`, or `None` if map is empty.  
+    /// Runs in O(log n).
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut deadlines = BstMap::from([(30, "backup"), (10, "flush")]);
+    /// assert!(deadlines.pop_first() == Some((10, "flush")));
+    /// assert!(deadlines.pop_first() == Some((30, "backup")));
+    /// assert!(deadlines.pop_first().is_none());
+    /// ```
+    pub fn pop_first(&mut self) -> Option<(T, V)> {
+        let action = Node::remove_position(&mut self.head, NodePosition::First);
+        self._handle_remove(action)
+    }
+
+    /// Removes the entry with the largest key and returns  
+    /// `Some((key, value))`, or `None` if map is empty.  
+    /// Runs in O(log n).
+    pub fn pop_last(&mut self) -> Option<(T, V)> {
+        let action = Node::remove_position(&mut self.head, NodePosition::Last);
+        self._handle_remove(action)
+    }
+
+    /// Removes the entry with the smallest key and returns  
+    /// `Some(value)`, or `None` if map is empty.  
+    /// Use `pop_first` to also get the key back.
+    pub fn remove_first(&mut self) -> Option<V> {
+        self.pop_first().map(|(_, value)| value)
+    }
+
+    /// Removes the entry with the largest key and returns  
+    /// `Some(value)`, or `None` if map is empty.  
+    /// Use `pop_last` to also get the key back.
+    pub fn remove_last(&mut self) -> Option<V> {
+        self.pop_last().map(|(_, value)| value)
+    }
+
+    /// Returns the entry with the smallest key,  
+    /// or `None` if map is empty.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::from([(1, "a"), (2, "b")]);
+    /// if let Some(mut entry) = map.first_entry() {
+    ///     *entry.get_mut() = "first";
+    /// }
+    /// assert!(map[&1] == "first");
+    /// assert!(map.first_entry().unwrap().remove_entry() == (1, "first"));
+    /// ```
+    pub fn first_entry(&mut self) -> Option<OccupiedEntry<'_, T, V, A>> {
+        OccupiedEntry::from_position(self, NodePosition::First)
+    }
+
+    /// Returns the entry with the largest key,  
+    /// or `None` if map is empty.
+    pub fn last_entry(&mut self) -> Option<OccupiedEntry<'_, T, V, A>> {
+        OccupiedEntry::from_position(self, NodePosition::Last)
+    }
+
+    /// Removes every entry from map, returning them as an `Iterator`
+    /// of owned key/value pairs in ascending key order.
+    /// Entries not consumed are dropped along with the `Iterator`.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::from([(2, 'b'), (1, 'a')]);
+    /// assert!(map.drain().eq([(1, 'a'), (2, 'b')]));
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn drain(&mut self) -> Iter<T, V, A> {
+        Iter::new(self.head.take(), std::mem::replace(&mut self.len, 0))
+    }
+
+    /// Keeps only the entries for which `func` returns `true`,
+    /// passing it each key and a mutable reference to its value
+    /// in ascending key order. Runs in O(n log n), leaving the nodes
+    /// of the entries kept where they are.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map: BstMap<_, _> = (0..10).map(|i| (i, i)).collect();
+    /// map.retain(|k, v| {
+    ///     *v *= 10;
+    ///     k % 3 == 0
+    /// });
+    /// assert!(map.into_iter().eq([(0, 0), (3, 30), (6, 60), (9, 90)]));
+    /// ```
+    pub fn retain<F>(&mut self, mut func: F)
+            where F: FnMut(&T, &mut V) -> bool {
+
+        self.extract_if(|key, value| !func(key, value)).for_each(drop);
+    }
+
+    /// Returns an `Iterator` which removes every entry for which `pred`
+    /// returns `true`, yielding the removed pairs in ascending key order.
+    /// `pred` is passed each key and a mutable reference to its value.
+    ///
+    /// The removal happens lazily: entries the `Iterator` never gets to,
+    /// because it was dropped early, are kept.
+    /// Every entry visited costs O(log n).
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map: BstMap<_, _> = (0..10).map(|i| (i, ())).collect();
+    /// let odds: Vec<_> = map.extract_if(|k, _| k % 2 == 1).map(|(k, _)| k).collect();
+    /// assert!(odds == [1, 3, 5, 7, 9]);
+    /// assert!(map.len() == 5);
+    /// ```
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, T, V, F, C, A>
+            where F: FnMut(&T, &mut V) -> bool {
+
+        ExtractIf::new(self, pred)
+    }
+
+    // Sorts the pairs and builds a balanced tree straight away,
+    // instead of inserting them one at a time. Input that is
+    // already sorted skips the sort.
+    fn from_pairs_in<I>(iter: I, cmp: C, alloc: A) -> Self
+            where I: IntoIterator<Item = (T, V)> {
+
+        let mut pairs: Vec<_> = iter.into_iter().collect();
+        if !pairs.windows(2).all(|pair| cmp.compare(&pair[0].0, &pair[1].0) == Ordering::Less) {
+            // Stable, so the last of any equal keys comes last.
+            pairs.sort_by(|a, b| cmp.compare(&a.0, &b.0));
+        }
+
+        // Like insert, the last value given for a key wins.
+        let mut unique: Vec<(T, V)> = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            match unique.last_mut() {
+                Some(last) if cmp.compare(&last.0, &pair.0) == Ordering::Equal => *last = pair,
+                _ => unique.push(pair),
+            }
+        }
+
+        Self {
+            len: unique.len(),
+            head: Node::from_sorted(unique, &alloc),
+            pool: NodePool::new(alloc),
+            cmp,
+        }
+    }
+
+    // Check what action we should take with return value
+    // from an insert call.
+    fn _handle_insert(&mut self, action: InsertAction<V>) -> Option<V> {
+        match action {
+            // A node was added, so increment our len.
+            InsertAction::Increment => {
+                self.len += 1;
+                None
+            }
+            // A value was clobbered, pass it along.
+            InsertAction::Replaced(value) => Some(value),
+            InsertAction::None => None,
+        }
+    }
+
+    // Check what action we should take with return value
+    // from a remove call.
+    fn _handle_remove(&mut self, action: RemoveAction<NodeBox<T, V, A>>) -> Option<(T, V)> {
+        match action {
+            // A node was removed, so decrement our len, hand its
+            // allocation to the pool and pass the key and value along.
+            RemoveAction::Removed(node) => {
+                self.len -= 1;
+                Some(self.pool.recycle(node, self.len))
+            }
+            RemoveAction::None => None,
+        }
+    }
+}
+
+impl<T: Debug, V: Debug, C, A: Allocator + Clone> BstMap<T, V, C, A> {
+    /// Writes the tree behind the map to `w` as a Graphviz digraph,
+    /// with every node labelled by its key and value.
+    /// Edges are labelled `L` and `R`, and missing children
+    /// are drawn as points.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let mut map = BstMap::new();
+    /// map.insert(1, "a");
+    /// let mut dot = Vec::new();
+    /// map.to_dot(&mut dot).unwrap();
+    /// assert!(String::from_utf8(dot).unwrap().starts_with("digraph BstMap {"));
+    /// ```
+    pub fn to_dot<W: std::io::Write>(&self, w: W) -> std::io::Result<()> {
+        self.to_dot_with(w, &DotOptions::new())
+    }
+
+    /// Writes the tree behind the map to `w` as a Graphviz digraph,
+    /// labelling nodes as set in `options`.
+    ///
+    /// ```
+    /// # use bstmap::{BstMap, DotOptions};
+    /// let mut map = BstMap::new();
+    /// map.insert(1, "a");
+    /// map.insert(2, "b");
+    /// let options = DotOptions::new().height(true).balance(true);
+    /// let mut dot = Vec::new();
+    /// map.to_dot_with(&mut dot, &options).unwrap();
+    /// assert!(String::from_utf8(dot).unwrap().contains(r#"1: \"a\"\nh=2 bf=-1"#));
+    /// ```
+    pub fn to_dot_with<W: std::io::Write>(&self, w: W, options: &DotOptions) -> std::io::Result<()> {
+        dot::write_dot(&self.head, w, options)
+    }
+}
+
+impl<T: Encode, V: Encode, C, A: Allocator + Clone> BstMap<T, V, C, A> {
+    /// Writes the map to `w` in the native snapshot format,
+    /// which `read_snapshot` reads back. Maps with a comparator of
+    /// their own are read back with `read_snapshot_with`.
+    /// Writes are buffered.
+    ///
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let map = BstMap::from([(1, "one".to_string()), (2, "two".to_string())]);
+    /// let mut bytes = Vec::new();
+    /// map.write_snapshot(&mut bytes).unwrap();
+    /// let copy = BstMap::<i32, String>::read_snapshot(&bytes[..]).unwrap();
+    /// assert!(copy == map);
+    /// ```
+    pub fn write_snapshot<W: std::io::Write>(&self, w: W) -> std::io::Result<()> {
+        snapshot::write(&self.head, self.len, w)
+    }
+}
+
+impl<T: Ord + Decode, V: Decode> BstMap<T, V> {
+    /// Reads a map written by `write_snapshot`, building a balanced
+    /// tree in O(n). Reads are not buffered, so pass in a `BufReader`
+    /// when reading from a file.
+    ///
+    /// Fails if the snapshot is truncated, corrupt,
+    /// or its keys are not in ascending order.
+    pub fn read_snapshot<R: std::io::Read>(r: R) -> Result<Self, SnapshotError> {
+        Self::read_snapshot_with(r, Natural)
+    }
+}
+
+impl<T: Decode, V: Decode, C: Compare<T>> BstMap<T, V, C> {
+    /// Same as `read_snapshot`, for a map which orders its keys with
+    /// `cmp`. The snapshot's keys have to be in ascending order by `cmp`,
+    /// as written by a map using the same comparator.
+    ///
+    /// ```
+    /// # use bstmap::{BstMap, Natural, Reverse};
+    /// let mut map = BstMap::with_comparator(Reverse(Natural));
+    /// map.extend([(1, 'a'), (2, 'b')]);
+    /// let mut bytes = Vec::new();
+    /// map.write_snapshot(&mut bytes).unwrap();
+    /// let copy = BstMap::read_snapshot_with(&bytes[..], Reverse(Natural)).unwrap();
+    /// assert!(copy == map);
+    /// ```
+    pub fn read_snapshot_with<R: std::io::Read>(r: R, cmp: C) -> Result<Self, SnapshotError> {
+        Self::read_snapshot_with_in(r, cmp, Global)
+    }
+}
+
+impl<T: Decode, V: Decode, C: Compare<T>, A: Allocator + Clone> BstMap<T, V, C, A> {
+    /// Same as `read_snapshot_with`, boxing the map's nodes in `alloc`.
+    pub fn read_snapshot_with_in<R: std::io::Read>(r: R, cmp: C, alloc: A) -> Result<Self, SnapshotError> {
+        let (head, len) = snapshot::read(r, &cmp, &alloc)?;
+        Ok(Self { len, head, pool: NodePool::new(alloc), cmp })
+    }
+}
+
+// Trait Impls
+impl<T, V, C, A: Allocator + Clone> Drop for BstMap<T, V, C, A> {
+    // The compiler generated drop would recurse once per level
+    // of nested Boxes.
+    fn drop(&mut self) {
+        Node::drop_tree(self.head.take());
+    }
+}
+
+impl<T, V, C: Compare<T> + Default> Default for BstMap<T, V, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+impl<T, V, C: Compare<T> + Default> FromIterator<(T, V)> for BstMap<T, V, C> {
+    fn from_iter<I: IntoIterator<Item = (T, V)>>(iter: I) -> Self {
+        Self::from_pairs_in(iter, C::default(), Global)
+    }
+}
+
+impl<T, V, C: Compare<T> + Clone, A: Allocator + Clone> Extend<(T, V)> for BstMap<T, V, C, A> {
+    // Builds the new pairs into a tree of their own and appends it,
+    // which joins the two in O(log n) when their keys don't overlap.
+    fn extend<I: IntoIterator<Item = (T, V)>>(&mut self, iter: I) {
+        let mut other = Self::from_pairs_in(iter, self.cmp.clone(), self.allocator().clone());
+        self.append(&mut other);
+    }
+}
+
+impl<'a, T, V, C, A> Extend<(&'a T, &'a V)> for BstMap<T, V, C, A>
+        where T: Copy, V: Copy, C: Compare<T> + Clone, A: Allocator + Clone {
+
+    fn extend<I: IntoIterator<Item = (&'a T, &'a V)>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().map(|(&key, &value)| (key, value)));
+    }
+}
+
+impl<T: Ord, V, const N: usize> From<[(T, V); N]> for BstMap<T, V> {
+    /// ```
+    /// # use bstmap::BstMap;
+    /// let map = BstMap::from([(2, "b"), (1, "a")]);
+    /// assert!(map.first_key_value() == Some((&1, &"a")));
+    /// ```
+    fn from(pairs: [(T, V); N]) -> Self {
+        Self::from_iter(pairs)
+    }
+}
+
+impl<T: Clone, V: Clone, C: Clone, A: Allocator + Clone> Clone for BstMap<T, V, C, A> {
+    // Copies the tree as is, so no comparisons or rebalancing are needed.
+    fn clone(&self) -> Self {
+        Self {
+            len: self.len,
+            head: Node::clone_tree(&self.head, self.pool.allocator()),
+            pool: NodePool::new(self.pool.allocator().clone()),
+            cmp: self.cmp.clone(),
+        }
+    }
+}
+
+// Maps compare by their pairs in key order, the shape
+// of their trees doesn't matter.
+
+impl<T: PartialEq, V: PartialEq, C, A: Allocator + Clone> PartialEq for BstMap<T, V, C, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, V: Eq, C, A: Allocator + Clone> Eq for BstMap<T, V, C, A> {}
+
+impl<T: PartialOrd, V: PartialOrd, C, A: Allocator + Clone> PartialOrd for BstMap<T, V, C, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Ord, V: Ord, C, A: Allocator + Clone> Ord for BstMap<T, V, C, A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Hash, V: Hash, C, A: Allocator + Clone> Hash for BstMap<T, V, C, A> {
+    // The len goes first so maps whose pairs run into each other,
+    // like in a tuple of maps, still hash differently.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len);
+        for pair in self {
+            pair.hash(state);
+        }
+    }
+}
+
+impl<T, V, C, A, Q> Index<&Q> for BstMap<T, V, C, A> 
+        where T: Borrow<Q>, C: Compare<T> + Compare<Q>, A: Allocator + Clone, Q: ?Sized {
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &Self::Output {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<T: Debug, V: Debug, C, A: Allocator + Clone> Display for BstMap<T, V, C, A> {
+    // Draws the tree with the default Renderer.
+    // An empty map is drawn as "(empty)".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Renderer::new().write(self, f)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use std::{collections::HashSet, sync::Arc};
+
+    // A Node as assert_avl_with sees it: its key, cached height,
+    // cached size if it keeps one, and children.
+    type Shape<'a, K, N> = (&'a K, u8, Option<usize>, Option<N>, Option<N>);
+
+    // Walks a tree checking ordering, cached heights and sizes, and the
+    // AVL balance invariant. Works for every tree in the crate, with shape
+    // taking one of its Nodes apart. Returns the height and size of the
+    // tree under node.
+    fn assert_avl_with<'a, K: 'a, N: Copy, C, F>(node: Option<N>, cmp: &C, shape: &F) -> (u8, usize)
+            where C: Compare<K>, F: Fn(N) -> Shape<'a, K, N> {
+
+        let Some(node) = node else { return (0, 0) };
+        let (key, height, size, left, right) = shape(node);
+        if let Some(left) = left {
+            assert!(cmp.compare(shape(left).0, key) == Ordering::Less);
+        }
+        if let Some(right) = right {
+            assert!(cmp.compare(shape(right).0, key) == Ordering::Greater);
+        }
+        let (left_height, left_size) = assert_avl_with(left, cmp, shape);
+        let (right_height, right_size) = assert_avl_with(right, cmp, shape);
+        assert!(left_height.abs_diff(right_height) <= 1);
+        assert!(height == 1 + left_height.max(right_height));
+        let total = 1 + left_size + right_size;
+        if let Some(size) = size {
+            assert!(size == total);
+        }
+        (height, total)
+    }
+
+    // Walks the whole tree checking ordering, cached heights and
+    // the AVL balance invariant. Returns the height of link.
+    fn assert_avl<T: Ord, V, A: Allocator + Clone>(link: &NodeLink<T, V, A>) -> u8 {
+        assert_avl_by(link, &Natural)
+    }
+
+    // Same as assert_avl, for keys ordered by cmp.
+    fn assert_avl_by<'a, T, V, C, A>(link: &'a NodeLink<T, V, A>, cmp: &C) -> u8
+            where C: Compare<T>, A: Allocator + Clone {
+
+        assert_avl_with(link.as_deref(), cmp, &|node: &'a Node<T, V, A>| {
+            (&node.key, node.height, Some(node.size), node.left.as_deref(), node.right.as_deref())
+        }).0
+    }
+
+    // Same as assert_avl, for an ArenaBstMap.
+    fn assert_arena_avl<T: Ord, V>(map: &ArenaBstMap<T, V>) -> u8 {
+        // Missing Nodes are NIL rather than None.
+        let link = |index| (index != arena::NIL).then_some(index);
+        assert_avl_with(link(map.root), &Natural, &|index| {
+            let node = map.node(index);
+            (&node.key, node.height, None, link(node.left), link(node.right))
+        }).0
+    }
+
+    // Same as assert_avl, for a PersistentBstMap.
+    fn assert_persistent_avl<'a, T, V, C: Compare<T>>(map: &'a PersistentBstMap<T, V, C>) -> u8 {
+        assert_avl_with(map.root.as_deref(), map.comparator(), &|node: &'a persistent::PersistentNode<T, V>| {
+            (&node.key, node.height, Some(node.size), node.left.as_deref(), node.right.as_deref())
+        }).0
+    }
+
+    // Addresses of every Node in a PersistentBstMap.
+    fn persistent_nodes<T, V, C>(map: &PersistentBstMap<T, V, C>) -> HashSet<*const persistent::PersistentNode<T, V>> {
+        let mut nodes = HashSet::new();
+        let mut stack: Vec<_> = map.root.iter().collect();
+        while let Some(node) = stack.pop() {
+            nodes.insert(Arc::as_ptr(node));
+            stack.extend(node.left.iter().chain(node.right.iter()));
+        }
+        nodes
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut map = BstMap::new();
+        map.insert(0, 0);
+        assert!(*map.get(&0).unwrap() == 0);
+        map.insert(1, 1);
+        assert!(*map.get(&1).unwrap() == 1);
+        map.insert(1, 2);
+        assert!(*map.get(&1).unwrap() == 2);
+    }
+
+    #[test]
+    fn insert_or() {
+        let mut map = BstMap::new();
+        map.insert(0, 0);
+        assert!(*map.get(&0).unwrap() == 0);
+        map.insert_or(0, 10, |v| { *v += 1; });
+        assert!(*map.get(&0).unwrap() == 1);
+    }
+
+    #[test]
+    fn clear() {
+        let mut map = BstMap::new();
+        map.insert(0, 0);
+        map.insert(1, 0);
+        map.insert(2, 0);
+        map.insert(3, 0);
+        map.insert(4, 0);
+        map.insert(5, 0);
+        map.insert(6, 0);
+        map.insert(7, 0);
+        assert!(!map.is_empty());
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut map = BstMap::new();
+        map.insert(0, 0);
+        let val = map.get_mut(&0).unwrap();
+        *val += 1;
+        assert!(*map.get(&0).unwrap() == 1);
+    }
+
+    #[test]
+    fn first_last_key_value() {
+        let mut map = BstMap::new();
+        map.insert(2, 2);
         map.insert(1, 1);
         map.insert(3, 3);
         let (key, value) = map.first_key_value().unwrap();
@@ -359,10 +1326,10 @@ mod tests {
         map.insert(3, "third first");
         map.insert(2, "second first");
 
-        let value = map.remove(99);
+        let value = map.remove(&99);
         assert!(value.is_none());
 
-        let value = map.remove(10).unwrap();
+        let value = map.remove(&10).unwrap();
         assert!(value == "head");
 
         let value = map.remove_first().unwrap();
@@ -388,4 +1355,1187 @@ mod tests {
 
         assert!(map.is_empty());
     }
+
+    #[test]
+    fn sorted_insert_stays_balanced() {
+        let mut map = BstMap::new();
+        for i in 0..1024 {
+            map.insert(i, i);
+        }
+        // A perfectly balanced tree of 1024 nodes is 11 high.
+        assert!(assert_avl(&map.head) == 11);
+        for i in (0..1024).rev() {
+            map.insert_or(i + 1024, i, |_| ());
+        }
+        assert!(assert_avl(&map.head) <= 12);
+        assert!(map.len() == 2048);
+        assert!(*map.get(&1500).unwrap() == 476);
+    }
+
+    #[test]
+    fn remove_stays_balanced() {
+        let mut map = BstMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        for i in (0..1000).step_by(3) {
+            assert!(map.remove(&i) == Some(i));
+            assert_avl(&map.head);
+        }
+        for _ in 0..100 {
+            map.remove_first();
+            map.remove_last();
+            assert_avl(&map.head);
+        }
+        assert!(map.len() == 466);
+        assert!(map.get(&3).is_none());
+        assert!(*map.get(&400).unwrap() == 400);
+    }
+
+    #[test]
+    fn get_mut_left_subtree() {
+        let mut map = BstMap::new();
+        for i in 0..16 {
+            map.insert(i, i);
+        }
+        for i in 0..16 {
+            *map.get_mut(&i).unwrap() += 100;
+        }
+        assert!(map.iter().all(|(k, v)| *v == *k + 100));
+    }
+
+    #[test]
+    fn large_map_small_stack() {
+        // 64 KiB is far too small for anything that recurses per node.
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(|| {
+                let mut map = BstMap::new();
+                for i in 0..200_000 {
+                    map.insert(i, i);
+                }
+                assert!(map.iter().count() == 200_000);
+                assert!(map.into_iter().count() == 200_000);
+
+                // Build a degenerate chain by hand, since the
+                // public API always keeps the tree balanced.
+                let mut map = BstMap::new();
+                for i in 0..200_000 {
+                    let mut node = Box::new(Node::new(i, i));
+                    node.left = map.head.take();
+                    map.head = Some(node);
+                    map.len += 1;
+                }
+                assert!(*map.get(&0).unwrap() == 0);
+                map.clear();
+
+                for i in 0..200_000 {
+                    let mut node = Box::new(Node::new(i, i));
+                    node.right = map.head.take();
+                    map.head = Some(node);
+                }
+                map.len = 200_000;
+                assert!(map.iter_mut().count() == 200_000);
+                // Drawing the whole chain would take quadratic space for its
+                // indentation, so only the top of it is drawn.
+                let display = Renderer::new().values(false).max_depth(64).render(&map);
+                assert!(display.lines().count() == 1 + 63 * 2);
+                assert!(display.matches('∅').count() == 63);
+                assert!(display.ends_with("└── 199936 …"));
+                drop(map);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn iter_double_ended() {
+        let mut map = BstMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+
+        let mut iter = map.iter();
+        assert!(iter.len() == 100);
+        assert!(iter.next() == Some((&0, &0)));
+        assert!(iter.next_back() == Some((&99, &99)));
+        assert!(iter.len() == 98);
+        // Both ends meet in the middle without yielding anything twice.
+        let rest: Vec<_> = iter.by_ref().map(|(k, _)| *k).collect();
+        assert!(rest == (1..99).collect::<Vec<_>>());
+        assert!(iter.next().is_none() && iter.next_back().is_none());
+
+        let mut iter = map.iter_mut();
+        for _ in 0..50 {
+            *iter.next_back().unwrap().1 += 1;
+            *iter.next().unwrap().1 += 1;
+        }
+        assert!(iter.next().is_none());
+        assert!(map.iter().all(|(k, v)| *v == *k + 1));
+
+        let mut iter = map.into_iter();
+        assert!(iter.next_back() == Some((99, 100)));
+        assert!(iter.next() == Some((0, 1)));
+        let mut front = 1;
+        let mut back = 98;
+        while iter.len() > 0 {
+            assert!(iter.next() == Some((front, front + 1)));
+            assert!(iter.next_back() == Some((back, back + 1)));
+            front += 1;
+            back -= 1;
+        }
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn into_iter_partial() {
+        let mut map = BstMap::new();
+        for i in 0..100 {
+            map.insert(i.to_string(), i);
+        }
+        // Dropping a half consumed iterator drops what's left.
+        let mut iter = map.into_iter();
+        assert!(iter.nth(10) == Some(("18".to_string(), 18)));
+        assert!(iter.len() == 89);
+        drop(iter);
+    }
+
+    #[test]
+    fn iter_sorted_order() {
+        // Keys inserted in a scrambled order.
+        let keys: Vec<u32> = (0..500).map(|i| (i * 7919) % 500).collect();
+        let mut map = BstMap::new();
+        for key in &keys {
+            map.insert(*key, *key * 2);
+        }
+        let sorted: Vec<u32> = (0..500).collect();
+
+        let iter_keys: Vec<u32> = map.iter().map(|(k, _)| *k).collect();
+        assert!(iter_keys == sorted);
+        let iter_keys: Vec<u32> = map.iter().rev().map(|(k, _)| *k).collect();
+        assert!(iter_keys.iter().rev().eq(sorted.iter()));
+
+        let iter_keys: Vec<u32> = map.iter_mut().map(|(k, _)| *k).collect();
+        assert!(iter_keys == sorted);
+        let iter_keys: Vec<u32> = map.iter_mut().rev().map(|(k, _)| *k).collect();
+        assert!(iter_keys.iter().rev().eq(sorted.iter()));
+
+        assert!((&map).into_iter().all(|(k, v)| *v == *k * 2));
+        let iter_keys: Vec<u32> = map.into_iter().map(|(k, _)| k).collect();
+        assert!(iter_keys == sorted);
+    }
+
+    #[test]
+    fn borrowed_lookups() {
+        let mut map = BstMap::new();
+        map.insert(String::from("one"), 1);
+        map.insert(String::from("two"), 2);
+        map.insert(String::from("three"), 3);
+
+        assert!(map.get("two") == Some(&2));
+        assert!(map["three"] == 3);
+        *map.get_mut("one").unwrap() += 10;
+        assert!(map[&String::from("one")] == 11);
+        assert!(map.remove("two") == Some(2));
+        assert!(map.get("two").is_none());
+        assert!(map.len() == 2);
+    }
+
+    #[test]
+    fn entry() {
+        // Entries can be sent and shared like &mut BstMap.
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<Entry<'_, String, Vec<u8>>>();
+        send_sync::<OccupiedError<'_, String, Vec<u8>>>();
+
+        let mut map = BstMap::new();
+        for i in 0..100 {
+            *map.entry(i % 10).or_insert(0) += 1;
+        }
+        assert!(map.len() == 10);
+        assert!(map.iter().all(|(_, v)| *v == 10));
+        assert_avl(&map.head);
+
+        for i in 100..200 {
+            let value = map.entry(i).or_insert_with_key(|k| *k * 2);
+            assert!(*value == i * 2);
+        }
+        assert!(map.len() == 110);
+        assert_avl(&map.head);
+
+        map.entry(5).and_modify(|v| *v = 50).or_insert(0);
+        map.entry(500).and_modify(|v| *v = 50).or_insert(0);
+        assert!(map[&5] == 50 && map[&500] == 0);
+
+        match map.entry(150) {
+            Entry::Occupied(mut entry) => {
+                assert!(*entry.key() == 150);
+                assert!(entry.insert(1) == 300);
+                *entry.get_mut() += 1;
+                assert!(*entry.get() == 2);
+                assert!(entry.remove_entry() == (150, 2));
+            }
+            Entry::Vacant(_) => panic!("150 should be occupied"),
+        }
+        match map.entry(150) {
+            Entry::Vacant(entry) => {
+                assert!(*entry.key() == 150);
+                *entry.insert(7) += 1;
+            }
+            Entry::Occupied(_) => panic!("150 should be vacant"),
+        }
+        assert!(map[&150] == 8);
+
+        for i in 0..200 {
+            if let Entry::Occupied(entry) = map.entry(i) {
+                entry.remove();
+            }
+            assert_avl(&map.head);
+        }
+        assert!(map.len() == 1);
+        assert!(*map.entry(1).or_default() == 0);
+
+        // Left-Right and Right-Left rotations move the new Node itself,
+        // the returned reference has to follow it.
+        let mut map = BstMap::new();
+        for i in [10, 5, 7, 20, 15, 30, 25] {
+            *map.entry(i).or_insert(0) += i;
+        }
+        assert!(map.iter().all(|(k, v)| k == v));
+        assert_avl(&map.head);
+    }
+
+    #[test]
+    fn range() {
+        use std::ops::Bound;
+
+        let mut map = BstMap::new();
+        for i in (0..200).step_by(2) {
+            map.insert(i, i);
+        }
+        let keys = |range: Range<'_, i32, i32>| range.map(|(k, _)| *k).collect::<Vec<_>>();
+
+        assert!(keys(map.range(10..20)) == [10, 12, 14, 16, 18]);
+        assert!(keys(map.range(9..=20)) == [10, 12, 14, 16, 18, 20]);
+        assert!(keys(map.range((Bound::Excluded(10), Bound::Included(15)))) == [12, 14]);
+        assert!(keys(map.range(..4)) == [0, 2]);
+        assert!(keys(map.range(195..)) == [196, 198]);
+        assert!(map.range(..).count() == 100);
+        assert!(map.range(11..12).next().is_none());
+        assert!(map.range(500..).next().is_none());
+        assert!(map.range(..-1).next_back().is_none());
+
+        // Ends meet without yielding anything twice.
+        let mut range = map.range(50..=60);
+        assert!(range.next() == Some((&50, &50)));
+        assert!(range.next_back() == Some((&60, &60)));
+        assert!(keys(range) == [52, 54, 56, 58]);
+        let mut range = map.range(50..=52);
+        assert!(range.next_back() == Some((&52, &52)));
+        assert!(range.next() == Some((&50, &50)));
+        assert!(range.next().is_none() && range.next_back().is_none());
+
+        for (_, value) in map.range_mut(100..110) {
+            *value = -1;
+        }
+        let mut range = map.range_mut(..=4);
+        *range.next_back().unwrap().1 = -2;
+        *range.next().unwrap().1 = -2;
+        *range.next().unwrap().1 = -2;
+        assert!(range.next().is_none());
+        assert!(keys(map.range(..)).len() == 100);
+        assert!(map.iter().filter(|(_, v)| **v == -1).count() == 5);
+        assert!(map.iter().filter(|(_, v)| **v == -2).count() == 3);
+
+        let mut map = BstMap::new();
+        map.insert(String::from("apple"), 1);
+        map.insert(String::from("banana"), 2);
+        map.insert(String::from("cherry"), 3);
+        let range = (Bound::Included("b"), Bound::Excluded("c"));
+        assert!(map.range::<str, _>(range).count() == 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "range start is greater than range end")]
+    fn range_backwards() {
+        let mut map = BstMap::new();
+        map.insert(1, 1);
+        let (start, end) = (5, 1);
+        map.range(start..end);
+    }
+
+    #[test]
+    fn order_statistics() {
+        let mut map = BstMap::new();
+        for i in (0..1000).rev() {
+            map.insert(i * 2, i);
+        }
+        assert_avl(&map.head);
+        assert!(map.head.as_ref().unwrap().size == 1000);
+
+        for i in 0..1000 {
+            assert!(map.get_index(i as usize) == Some((&(i * 2), &i)));
+            assert!(map.rank(&(i * 2)) == i as usize);
+            assert!(map.rank(&(i * 2 + 1)) == i as usize + 1);
+        }
+        assert!(map.get_index(1000).is_none());
+        assert!(map.rank(&-5) == 0);
+
+        let keys: Vec<_> = map.iter_from_index(995).map(|(k, _)| *k).collect();
+        assert!(keys == [1990, 1992, 1994, 1996, 1998]);
+        let mut iter = map.iter_from_index(500);
+        assert!(iter.len() == 500);
+        assert!(iter.next_back() == Some((&1998, &999)));
+        assert!(iter.next() == Some((&1000, &500)));
+        assert!(map.iter_from_index(0).count() == 1000);
+        assert!(map.iter_from_index(1000).next().is_none());
+        assert!(map.iter_from_index(5000).next_back().is_none());
+
+        assert!(map.remove_index(10) == Some((20, 10)));
+        assert!(map.remove_index(999).is_none());
+        assert!(map.remove_index(998) == Some((1998, 999)));
+        assert_avl(&map.head);
+        for _ in 0..100 {
+            map.remove_index(map.len() / 2);
+            map.remove_first();
+            map.remove(&map.get_index(300).map(|(k, _)| *k).unwrap());
+            *map.entry(-1).or_insert(0) += 1;
+            assert_avl(&map.head);
+        }
+        assert!(map.len() == 798);
+        assert!(map.head.as_ref().unwrap().size == 798);
+        assert!(map.get_index(0) == Some((&-1, &1)));
+    }
+
+    #[test]
+    fn neighbors() {
+        let mut map = BstMap::new();
+        for i in 1..100 {
+            map.insert(i * 10, i);
+        }
+
+        for key in 0..1000 {
+            let expect = |pred: &dyn Fn(i32) -> bool, rev: bool| {
+                let mut keys: Vec<i32> = (1..100).map(|i| i * 10).filter(|k| pred(*k)).collect();
+                if rev { keys.reverse() }
+                keys.first().map(|k| (*k, *k / 10))
+            };
+            let found = |pair: Option<(&i32, &i32)>| pair.map(|(k, v)| (*k, *v));
+            assert!(found(map.floor(&key)) == expect(&|k| k <= key, true));
+            assert!(found(map.lower(&key)) == expect(&|k| k < key, true));
+            assert!(found(map.ceiling(&key)) == expect(&|k| k >= key, false));
+            assert!(found(map.higher(&key)) == expect(&|k| k > key, false));
+        }
+
+        *map.floor_mut(&15).unwrap().1 = -1;
+        *map.ceiling_mut(&15).unwrap().1 = -2;
+        *map.lower_mut(&500).unwrap().1 = -3;
+        *map.higher_mut(&500).unwrap().1 = -4;
+        assert!(map[&10] == -1 && map[&20] == -2 && map[&490] == -3 && map[&510] == -4);
+        assert!(map.lower_mut(&10).is_none() && map.higher_mut(&990).is_none());
+
+        let empty: BstMap<i32, i32> = BstMap::new();
+        assert!(empty.floor(&1).is_none() && empty.ceiling(&1).is_none());
+    }
+
+    #[test]
+    fn split_off() {
+        for at in [-1, 0, 1, 250, 499, 500, 999, 1000, 1500] {
+            let mut map = BstMap::new();
+            for i in (0..1000).step_by(2) {
+                map.insert(i, i);
+            }
+            let mut tail = map.split_off(&at);
+            assert_avl(&map.head);
+            assert_avl(&tail.head);
+            assert!(map.len() + tail.len() == 500);
+            assert!(map.iter().all(|(k, _)| *k < at));
+            assert!(tail.iter().all(|(k, _)| *k >= at));
+            assert!(map.len() == map.iter().count());
+            assert!(tail.len() == tail.iter().count());
+
+            // And back together again
+            map.append(&mut tail);
+            assert_avl(&map.head);
+            assert!(tail.is_empty());
+            assert!(map.iter().map(|(k, _)| *k).eq((0..1000).step_by(2)));
+        }
+    }
+
+    #[test]
+    fn append() {
+        let mut map = BstMap::new();
+        let mut other = BstMap::new();
+        for i in 0..100 {
+            map.insert(i, "map");
+        }
+        for i in 1000..1003 {
+            other.insert(i, "other");
+        }
+        // Small disjoint tree joined onto a big one, on both sides.
+        map.append(&mut other);
+        assert_avl(&map.head);
+        for i in -3..0 {
+            other.insert(i, "other");
+        }
+        map.append(&mut other);
+        assert_avl(&map.head);
+        assert!(map.len() == 106 && other.is_empty());
+
+        // Overlapping ranges, other wins on duplicates.
+        for i in (50..150).step_by(3) {
+            other.insert(i, "other");
+        }
+        map.append(&mut other);
+        assert_avl(&map.head);
+        assert!(map.len() == 106 + 17);
+        assert!(map[&50] == "other" && map[&51] == "map" && map[&149] == "other");
+        assert!(map.iter().map(|(k, _)| *k).collect::<Vec<_>>().windows(2).all(|w| w[0] < w[1]));
+
+        // Other mostly overlapping a small middle.
+        for i in 0..2000 {
+            other.insert(i, "big");
+        }
+        let mut small = BstMap::new();
+        small.insert(10, "small");
+        small.insert(20, "small");
+        small.append(&mut other);
+        assert_avl(&small.head);
+        assert!(small.len() == 2000 && small.iter().all(|(_, v)| *v == "big"));
+
+        let mut empty = BstMap::new();
+        empty.append(&mut small);
+        assert!(empty.len() == 2000 && small.is_empty());
+        empty.append(&mut small);
+        assert!(empty.len() == 2000);
+    }
+
+    #[test]
+    fn no_debug_bounds() {
+        // Neither of these implement Debug.
+        #[derive(PartialEq, Eq, PartialOrd, Ord)]
+        struct Key(u32);
+        type Callback = Box<dyn Fn(u32) -> u32>;
+
+        let mut map: BstMap<Key, Callback> = BstMap::new();
+        map.insert(Key(1), Box::new(|x| x + 1));
+        map.insert(Key(2), Box::new(|x| x * 2));
+        map.entry(Key(3)).or_insert_with(|| Box::new(|x| x * x));
+        assert!(map[&Key(3)](4) == 16);
+        assert!(map.range(Key(2)..).map(|(_, f)| f(10)).sum::<u32>() == 120);
+        assert!(map.remove(&Key(1)).unwrap()(1) == 2);
+        let tail = map.split_off(&Key(3));
+        assert!(map.into_iter().chain(tail).map(|(k, _)| k.0).eq([2, 3]));
+    }
+
+    #[test]
+    fn render() {
+        let mut map = BstMap::new();
+        assert!(map.to_string() == "(empty)");
+
+        for key in [4, 2, 6, 1, 3, 7] {
+            map.insert(key, key * 10);
+        }
+        assert!(map.to_string() ==
+            "4: 40\n\
+             ├── 2: 20\n\
+             │   ├── 1: 10\n\
+             │   └── 3: 30\n\
+             └── 6: 60\n    \
+                 ├── ∅\n    \
+                 └── 7: 70");
+
+        let ascii = Renderer::new().charset(Charset::Ascii).values(false);
+        assert!(ascii.render(&map) == "4\n|-- 2\n|   |-- 1\n|   `-- 3\n`-- 6\n    |-- -\n    `-- 7");
+        assert!(ascii.clone().max_depth(2).render(&map) == "4\n|-- 2 ...\n`-- 6 ...");
+
+        let top_down = Renderer::new().layout(Layout::TopDown).values(false);
+        assert!(top_down.render(&map) == [
+            "      4",
+            "  ┌───┴─┐",
+            "  2     6",
+            "┌─┴─┐   └─┐",
+            "1   3     7",
+        ].join("\n"));
+        assert!(top_down.max_depth(1).render(&map) == "4 …");
+
+        let pointers = Renderer::new().pointers(true).render(&map);
+        let root: &Node<_, _, _> = map.head.as_ref().unwrap();
+        assert!(pointers.starts_with(&format!("4: 40 @ {:p}\n", root)));
+
+        // A failing Debug is reported, not panicked on.
+        struct Broken;
+        impl Debug for Broken {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                Err(std::fmt::Error)
+            }
+        }
+        let mut map = BstMap::new();
+        map.insert(1, Broken);
+        assert!(Renderer::new().write(&map, &mut String::new()).is_err());
+        assert!(Renderer::new().values(false).render(&map) == "1");
+        assert!(std::panic::catch_unwind(|| Renderer::new().render(&map)).is_err());
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut dot = Vec::new();
+        BstMap::<u32, u32>::new().to_dot(&mut dot).unwrap();
+        assert!(dot == b"digraph BstMap {\n    node [shape=box];\n}\n");
+
+        let mut map = BstMap::new();
+        for key in ["b", "a", "c", "d"] {
+            map.insert(key, "q\"\\");
+        }
+        let options = DotOptions::new().values(false).height(true).size(true).balance(true);
+        let mut dot = Vec::new();
+        map.to_dot_with(&mut dot, &options).unwrap();
+        assert!(String::from_utf8(dot).unwrap() == [
+            "digraph BstMap {",
+            "    node [shape=box];",
+            r#"    n0 [label="\"b\"\nh=3 size=4 bf=-1"];"#,
+            "    n0 -> n1 [label=\"L\"];",
+            "    n0 -> n2 [label=\"R\"];",
+            r#"    n1 [label="\"a\"\nh=1 size=1 bf=+0"];"#,
+            "    n3 [shape=point];",
+            "    n1 -> n3 [label=\"L\"];",
+            "    n4 [shape=point];",
+            "    n1 -> n4 [label=\"R\"];",
+            r#"    n2 [label="\"c\"\nh=2 size=2 bf=-1"];"#,
+            "    n5 [shape=point];",
+            "    n2 -> n5 [label=\"L\"];",
+            "    n2 -> n6 [label=\"R\"];",
+            r#"    n6 [label="\"d\"\nh=1 size=1 bf=+0"];"#,
+            "    n7 [shape=point];",
+            "    n6 -> n7 [label=\"L\"];",
+            "    n8 [shape=point];",
+            "    n6 -> n8 [label=\"R\"];",
+            "}",
+            "",
+        ].join("\n"));
+
+        let mut dot = Vec::new();
+        map.to_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains(r#"    n6 [label="\"d\": \"q\\\"\\\\\""];"#));
+        assert!(dot.matches("[shape=point]").count() == map.len() + 1);
+
+        // A failing Debug is reported, not panicked on.
+        #[derive(PartialEq, Eq, PartialOrd, Ord)]
+        struct Broken;
+        impl Debug for Broken {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                Err(std::fmt::Error)
+            }
+        }
+        let mut map = BstMap::new();
+        map.insert(Broken, 1);
+        assert!(map.to_dot(&mut Vec::new()).is_err());
+        assert!(map.to_dot_with(&mut Vec::new(), &DotOptions::new().values(false)).is_err());
+    }
+
+    #[test]
+    fn clone_and_compare() {
+        use std::collections::{HashSet, hash_map::DefaultHasher};
+
+        let mut map = BstMap::new();
+        for i in 0..100 {
+            map.insert(i, i.to_string());
+        }
+        let copy = map.clone();
+        assert_avl(&copy.head);
+        assert!(copy == map && copy.len() == 100);
+        // Same shape, down to every Node.
+        let shape = |map: &BstMap<i32, String>| Renderer::new().values(false).render(map);
+        assert!(shape(&copy) == shape(&map));
+
+        // Same pairs, different shape.
+        let mut reversed = BstMap::new();
+        for i in (0..100).rev() {
+            reversed.insert(i, i.to_string());
+        }
+        assert!(shape(&reversed) != shape(&map));
+        assert!(reversed == map);
+
+        let hash = |map: &BstMap<i32, String>| {
+            let mut hasher = DefaultHasher::new();
+            map.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert!(hash(&reversed) == hash(&map));
+
+        *reversed.get_mut(&50).unwrap() = "fifty".to_string();
+        assert!(reversed != map);
+        assert!(reversed > map && map.cmp(&reversed) == Ordering::Less);
+        reversed.remove(&99);
+        assert!(reversed != map);
+        assert!(BstMap::<i32, String>::default() < map);
+
+        let mut sets = HashSet::new();
+        sets.insert(map.clone());
+        sets.insert(reversed.clone());
+        sets.insert(map);
+        assert!(sets.len() == 2);
+
+        #[derive(Default)]
+        struct Holder {
+            map: BstMap<u8, u8>,
+        }
+        assert!(Holder::default().map.is_empty());
+    }
+
+    #[test]
+    fn collect_and_extend() {
+        let map = BstMap::from_sorted_iter((0..1000).map(|i| (i, i)));
+        assert!(assert_avl(&map.head) == 10);
+        assert!(map.iter().map(|(k, _)| *k).eq(0..1000));
+
+        // Unsorted with duplicates, the last value for a key wins.
+        let map: BstMap<_, _> = [(3, 'a'), (1, 'b'), (3, 'c'), (2, 'd'), (1, 'e')].into_iter().collect();
+        assert_avl(&map.head);
+        assert!(map.len() == 3);
+        assert!(map.iter().eq([(&1, &'e'), (&2, &'d'), (&3, &'c')]));
+        let map: BstMap<u8, u8> = std::iter::empty().collect();
+        assert!(map.is_empty() && map.head.is_none());
+
+        let mut map = BstMap::from([(5, 50), (1, 10)]);
+        map.extend([(9, 90), (5, 55)]);
+        map.extend(&BstMap::from([(7, 70), (0, 0)]));
+        assert_avl(&map.head);
+        assert!(map.len() == 5);
+        assert!(map.iter().eq([(&0, &0), (&1, &10), (&5, &55), (&7, &70), (&9, &90)]));
+
+        let mut map = BstMap::new();
+        map.extend((0..100).map(|i| (i, ())));
+        map.extend((100..200).map(|i| (i, ())));
+        assert_avl(&map.head);
+        assert!(map.len() == 200 && map.iter().map(|(k, _)| *k).eq(0..200));
+    }
+
+    #[test]
+    #[should_panic(expected = "not in strictly ascending order")]
+    fn from_sorted_iter_unsorted() {
+        BstMap::from_sorted_iter([(1, ()), (3, ()), (2, ())]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_json_round_trip() {
+        let map: BstMap<String, Vec<u32>> = (0..20).map(|i| (format!("key{:02}", i), vec![i; 2])).collect();
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json.starts_with(r#"{"key00":[0,0],"key01":[1,1],"#));
+        let back: BstMap<String, Vec<u32>> = serde_json::from_str(&json).unwrap();
+        assert_avl(&back.head);
+        assert!(back == map);
+
+        let empty: BstMap<u8, u8> = serde_json::from_str("{}").unwrap();
+        assert!(empty.is_empty() && serde_json::to_string(&empty).unwrap() == "{}");
+
+        // Integer keys go through JSON as strings.
+        let map = BstMap::from([(10, 'a'), (2, 'b')]);
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json == r#"{"2":"b","10":"a"}"#);
+        assert!(serde_json::from_str::<BstMap<i32, char>>(&json).unwrap() == map);
+
+        let json = r#"{"x": 1, "y": 2, "x": 3}"#;
+        let map: BstMap<String, u32> = serde_json::from_str(json).unwrap();
+        assert!(map.len() == 2 && map["x"] == 3);
+        let mut de = serde_json::Deserializer::from_str(json);
+        let err = BstMap::<String, u32>::deserialize_with_policy(&mut de, DuplicateKeys::Error).unwrap_err();
+        assert!(err.to_string().contains("duplicate key"));
+        assert!(serde_json::from_str::<BstMap<String, u32>>("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn snapshot() {
+        let map: BstMap<u64, (String, Option<char>)> = (0..10_000u64)
+            .map(|i| (i * 7, (i.to_string(), char::from_u32(i as u32))))
+            .collect();
+        let mut bytes = Vec::new();
+        map.write_snapshot(&mut bytes).unwrap();
+        let copy = BstMap::<u64, (String, Option<char>)>::read_snapshot(&bytes[..]).unwrap();
+        assert!(assert_avl(&copy.head) == 14);
+        assert!(copy == map);
+
+        let read = |bytes: &[u8]| BstMap::<u64, (String, Option<char>)>::read_snapshot(bytes);
+        let mut corrupt = bytes.clone();
+        corrupt[100] ^= 1;
+        assert!(matches!(read(&corrupt), Err(SnapshotError::ChecksumMismatch)));
+        let mut corrupt = bytes.clone();
+        corrupt[0] = b'X';
+        assert!(matches!(read(&corrupt), Err(SnapshotError::BadMagic)));
+        let mut corrupt = bytes.clone();
+        corrupt[4] = 9;
+        assert!(matches!(read(&corrupt), Err(SnapshotError::UnsupportedVersion(9))));
+        assert!(matches!(read(&bytes[..bytes.len() - 1]), Err(SnapshotError::Io(_))));
+        assert!(matches!(read(&bytes[..20]), Err(SnapshotError::Io(_))));
+
+        // Swap the two entries of a valid snapshot and fix up its checksum.
+        let mut bytes = Vec::new();
+        BstMap::from([(1u8, 'a'), (2u8, 'b')]).write_snapshot(&mut bytes).unwrap();
+        assert!(bytes.len() == 4 + 2 + 8 + 2 * 5 + 8);
+        bytes[14..24].rotate_left(5);
+        let body = bytes.len() - 8;
+        let hash = snapshot::fnv1a(0xcbf2_9ce4_8422_2325, &bytes[..body]);
+        bytes[body..].copy_from_slice(&hash.to_le_bytes());
+        let unsorted = BstMap::<u8, char>::read_snapshot(&bytes[..]);
+        assert!(matches!(unsorted, Err(SnapshotError::Unsorted)));
+
+        let mut bytes = Vec::new();
+        BstMap::<String, ()>::new().write_snapshot(&mut bytes).unwrap();
+        assert!(BstMap::<String, ()>::read_snapshot(&bytes[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn arena_insert_get_remove() {
+        let mut map = ArenaBstMap::new();
+        for i in 0..1000 {
+            map.insert(i * 2 % 1001, i);
+        }
+        assert!(map.len() == 1000);
+        assert!(assert_arena_avl(&map) <= 11);
+        assert!(map.get(&2) == Some(&1) && map.get(&1).is_some());
+        assert!(map.insert(2, 5) == Some(1));
+        *map.get_mut(&2).unwrap() += 1;
+        assert!(map.get(&2) == Some(&6) && map.len() == 1000);
+        assert!(map.first_key_value().map(|(k, _)| *k) == Some(0));
+        assert!(map.last_key_value().map(|(k, _)| *k) == Some(1000));
+
+        for i in (0..1001).step_by(3) {
+            let expected = map.get(&i).copied();
+            assert!(map.remove(&i) == expected);
+            assert!(map.remove(&i).is_none());
+        }
+        assert_arena_avl(&map);
+        // 999 was never inserted.
+        assert!(map.len() == 1000 - 333);
+        assert!(map.iter().map(|(k, _)| *k).eq((0..1001).filter(|k| k % 3 != 0)));
+    }
+
+    #[test]
+    fn arena_slots_are_reused() {
+        let mut map = ArenaBstMap::with_capacity(64);
+        for round in 0..10 {
+            for i in 0..64 {
+                map.insert(i, round);
+            }
+            for i in 0..64 {
+                assert!(map.remove(&i) == Some(round));
+            }
+        }
+        assert!(map.is_empty() && map.root == arena::NIL);
+        // Every round reused the slots freed by the one before.
+        assert!(map.slots.len() == 64 && map.capacity() == 64);
+
+        map.insert(1, 1);
+        map.clear();
+        assert!(map.is_empty() && map.iter().next().is_none());
+    }
+
+    #[test]
+    fn arena_strings() {
+        let mut map = ArenaBstMap::new();
+        for word in ["pear", "apple", "fig", "kiwi", "date"] {
+            map.insert(word.to_string(), word.len());
+        }
+        assert!(map.get("fig") == Some(&3));
+        assert!(map.remove("apple") == Some(5));
+        let words: Vec<_> = map.iter().map(|(k, _)| k.as_str()).collect();
+        assert!(words == ["date", "fig", "kiwi", "pear"]);
+        assert!(map.iter().len() == 4);
+        assert!(format!("{:?}", map) == r#"{"date": 4, "fig": 3, "kiwi": 4, "pear": 4}"#);
+    }
+
+    #[test]
+    fn bulk_removal() {
+        let mut map: BstMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        map.retain(|k, v| {
+            *v += 1;
+            k % 4 != 0
+        });
+        assert_avl(&map.head);
+        assert!(map.len() == 750);
+        assert!(map.iter().all(|(k, v)| k % 4 != 0 && *v == k + 1));
+
+        // Stopping early keeps everything not yet visited.
+        let mut extract = map.extract_if(|k, _| k % 2 == 0);
+        assert!(extract.next() == Some((2, 3)));
+        assert!(extract.next() == Some((6, 7)));
+        assert!(extract.size_hint() == (0, Some(745)));
+        assert_avl(&map.head);
+        assert!(map.len() == 748 && map.get(&10) == Some(&11) && map.get(&6).is_none());
+
+        let removed: Vec<_> = map.extract_if(|k, _| *k >= 500).collect();
+        assert!(removed.len() == 375 && removed.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_avl(&map.head);
+        assert!(map.len() == 373 && map.last_key_value() == Some((&499, &500)));
+
+        // Everything extracted, and nothing.
+        let mut copy = map.clone();
+        assert!(copy.extract_if(|_, _| true).count() == 373);
+        assert!(copy.is_empty() && copy.head.is_none());
+        assert!(map.extract_if(|_, _| false).next().is_none());
+        assert!(map.len() == 373);
+
+        let mut drain = map.drain();
+        assert!(drain.next() == Some((1, 2)));
+        assert!(drain.next_back() == Some((499, 500)));
+        drop(drain);
+        assert!(map.is_empty() && map.head.is_none());
+        map.insert(1, 1);
+        assert!(map.drain().collect::<Vec<_>>() == [(1, 1)]);
+    }
+
+    #[test]
+    fn extract_if_panic() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let mut map: BstMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            map.retain(|k, _| if *k == 50 { panic!() } else { k % 2 == 0 });
+        }));
+        assert!(result.is_err());
+        // Odd keys before 50 are gone, and no entry is lost:
+        // 50 itself and everything after it is untouched.
+        assert_avl(&map.head);
+        assert!(map.len() == 25 + 50);
+        assert!(map.get(&49).is_none() && map.get(&50) == Some(&50) && map.get(&51).is_some());
+        assert!(map.range(50..).map(|(k, _)| *k).eq(50..100));
+    }
+
+    #[test]
+    fn pop_and_end_entries() {
+        let mut map: BstMap<_, _> = (0..100).map(|i| (i, i * 2)).collect();
+        assert!(map.pop_first() == Some((0, 0)));
+        assert!(map.pop_last() == Some((99, 198)));
+        assert!(map.len() == 98);
+        assert_avl(&map.head);
+
+        let mut entry = map.first_entry().unwrap();
+        assert!(*entry.key() == 1 && *entry.get() == 2);
+        *entry.get_mut() = 100;
+        assert!(map.get(&1) == Some(&100));
+        assert!(map.last_entry().unwrap().remove() == 196);
+        assert!(map.len() == 97 && map.last_key_value() == Some((&97, &194)));
+        assert_avl(&map.head);
+
+        while let Some(entry) = map.last_entry() {
+            let (key, _) = entry.remove_entry();
+            assert!(map.iter().all(|(k, _)| *k < key));
+        }
+        assert!(map.is_empty() && map.pop_first().is_none() && map.pop_last().is_none());
+        assert!(map.first_entry().is_none() && map.last_entry().is_none());
+    }
+
+    #[test]
+    fn insert_returns_old_value() {
+        let mut map = BstMap::new();
+        for i in 0..100 {
+            assert!(map.insert(i, i).is_none());
+        }
+        for i in 0..100 {
+            assert!(map.insert(i, i + 1) == Some(i));
+        }
+        assert!(map.len() == 100);
+        assert_avl(&map.head);
+
+        assert!(map.try_insert(100, 0).ok().map(|v| *v) == Some(0));
+        let err = map.try_insert(50, 0).unwrap_err();
+        assert!(*err.entry.key() == 50 && err.value == 0 && *err.entry.get() == 51);
+        assert!(err.to_string() == "failed to insert 0, key 50 already exists with value 51");
+        assert!(map.len() == 101 && map[&50] == 51);
+
+        // Keys equal by Ord, told apart by their tag.
+        #[derive(Debug)]
+        struct Tagged(u8, &'static str);
+        impl PartialEq for Tagged { fn eq(&self, other: &Self) -> bool { self.0 == other.0 } }
+        impl Eq for Tagged {}
+        impl PartialOrd for Tagged { fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) } }
+        impl Ord for Tagged { fn cmp(&self, other: &Self) -> Ordering { self.0.cmp(&other.0) } }
+
+        let mut map = BstMap::new();
+        map.insert(Tagged(1, "old"), ());
+        assert!(map.insert(Tagged(1, "new"), ()) == Some(()));
+        assert!(map.first_key_value().unwrap().0 .1 == "old");
+        assert!(map.replace_key(Tagged(1, "new")).unwrap().1 == "old");
+        assert!(map.first_key_value().unwrap().0 .1 == "new");
+        assert!(map.replace_key(Tagged(2, "none")).is_none() && map.len() == 1);
+    }
+
+    #[test]
+    fn reserved_capacity() {
+        let mut map = BstMap::with_capacity(100);
+        assert!(map.capacity() == 100);
+        for i in 0..100 {
+            assert!(map.insert_fallible(i, i) == Ok(None));
+            assert!(map.capacity() == 100);
+        }
+        // Existing keys don't need a node, so none is reserved.
+        assert!(map.insert_fallible(5, 6) == Ok(Some(5)));
+        map.insert_or_fallible(5, 0, |v| *v += 1).unwrap();
+        assert!(map[&5] == 7 && map.len() == 100 && map.capacity() == 100);
+        assert_avl(&map.head);
+
+        // Removing hands nodes back to the pool, every way it's done.
+        assert!(map.remove(&0) == Some(0));
+        assert!(map.pop_first() == Some((1, 1)) && map.pop_last() == Some((99, 99)));
+        assert!(map.remove_index(0) == Some((2, 2)));
+        assert!(map.entry(3).or_default() == &mut 3);
+        if let Entry::Occupied(entry) = map.entry(3) {
+            assert!(entry.remove_entry() == (3, 3));
+        }
+        assert!(map.len() == 95 && map.capacity() == 100);
+
+        // Inserting past capacity still works, and removing
+        // doesn't keep more spares than were reserved.
+        for i in 100..200 {
+            map.insert(i, i);
+        }
+        map.clear();
+        assert!(map.capacity() <= 100);
+        map.shrink_to_fit();
+        assert!(map.capacity() == 0);
+
+        let err = map.try_reserve(usize::MAX - 10).unwrap_err();
+        assert!(err.kind() == TryReserveErrorKind::CapacityOverflow);
+        assert!(map.try_reserve(10).is_ok() && map.capacity() == 10);
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn custom_allocator() {
+        use std::{alloc::{AllocError, Layout}, cell::Cell, ptr::NonNull};
+
+        // Counts live allocations, leaving the actual work to Global.
+        #[derive(Clone, Copy)]
+        struct Counting<'a>(&'a Cell<usize>);
+
+        unsafe impl Allocator for Counting<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.set(self.0.get() - 1);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let live = Cell::new(0);
+        let mut map = BstMap::new_in(Counting(&live));
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        assert!(live.get() == 100);
+        map.remove(&0);
+        assert!(live.get() == 99);
+        map.reserve(10);
+        assert!(live.get() == 109 && map.capacity() == 109);
+
+        // Every other way of making Nodes goes through the allocator too.
+        let copy = map.clone();
+        assert!(live.get() == 208 && copy == map);
+        let mut high = map.split_off(&50);
+        high.extend((200..250).map(|i| (i, i)));
+        high.retain(|k, _| k % 2 == 0);
+        assert_avl(&high.head);
+        if let Entry::Vacant(entry) = high.entry(1000) {
+            entry.insert(0);
+        }
+        assert!(high.insert_fallible(1001, 0) == Ok(None));
+
+        drop((map, copy, high));
+        assert!(live.get() == 0);
+    }
+
+    #[test]
+    fn comparator() {
+        use std::ops::Bound;
+
+        let mut map = BstMap::with_comparator(Reverse(Natural));
+        for i in 0..100 {
+            map.insert(i, i * 10);
+        }
+        assert_avl_by(&map.head, &Reverse(Natural));
+        assert!(map.iter().map(|(k, _)| *k).eq((0..100).rev()));
+        assert!(map.first_key_value() == Some((&99, &990)));
+        assert!(map.get(&42) == Some(&420) && map.rank(&97) == 2);
+        assert!(map.remove(&50) == Some(500) && map.get(&50).is_none());
+        // Neighbors and ranges follow the map's order, not Ord's.
+        assert!(map.floor(&50) == Some((&51, &510)));
+        assert!(map.higher(&50) == Some((&49, &490)));
+        let range = (Bound::Included(60), Bound::Included(57));
+        assert!(map.range(range).map(|(k, _)| *k).eq([60, 59, 58, 57]));
+
+        let mut low = map.split_off(&10);
+        assert!(map.len() == 88 && low.len() == 11);
+        assert!(low.first_key_value() == Some((&10, &100)));
+        low.extend([(5, 0), (150, 0)]);
+        map.append(&mut low);
+        assert_avl_by(&map.head, &Reverse(Natural));
+        assert!(map.len() == 100 && map[&5] == 0);
+        assert!(map.first_key_value() == Some((&150, &0)));
+
+        let collected: BstMap<_, _, Reverse> = (0..10).map(|i| (i, ())).collect();
+        assert!(collected.iter().map(|(k, _)| *k).eq((0..10).rev()));
+
+        // Keys that compare equal share an entry.
+        let mut words = BstMap::with_comparator(|a: &&str, b: &&str| {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        });
+        for word in ["Apple", "banana", "apple", "BANANA", "cherry"] {
+            *words.entry(word).or_insert(0) += 1;
+        }
+        assert!(words.len() == 3);
+        assert!(words.get(&"APPLE") == Some(&2) && words[&"Banana"] == 2);
+        assert!(words.iter().map(|(k, _)| *k).eq(["Apple", "banana", "cherry"]));
+
+        // Snapshots are read back in the order they were written in.
+        let mut bytes = Vec::new();
+        map.write_snapshot(&mut bytes).unwrap();
+        assert!(matches!(BstMap::<i32, i32>::read_snapshot(&bytes[..]), Err(SnapshotError::Unsorted)));
+        let copy = BstMap::read_snapshot_with(&bytes[..], Reverse(Natural)).unwrap();
+        assert_avl_by(&copy.head, &Reverse(Natural));
+        assert!(copy == map && copy.first_key_value() == Some((&150, &0)));
+    }
+
+    #[test]
+    fn persistent_versions_are_independent() {
+        let mut versions = vec![PersistentBstMap::new()];
+        for i in 0..500 {
+            let next = versions.last().unwrap().insert(i * 7 % 500, i);
+            versions.push(next);
+        }
+        for i in (0..500).step_by(2) {
+            let next = versions.last().unwrap().remove(&i);
+            versions.push(next);
+        }
+
+        // Every version still holds exactly what it did when it was made.
+        for (n, map) in versions.iter().enumerate() {
+            assert_persistent_avl(map);
+            let inserted = n.min(500);
+            let removed = n.saturating_sub(500);
+            assert!(map.len() == inserted - removed);
+            let mut expected: Vec<_> = (0..inserted).map(|i| (i * 7 % 500, i)).collect();
+            expected.sort();
+            expected.retain(|(k, _)| k % 2 == 1 || *k >= removed * 2);
+            assert!(map.iter().map(|(k, v)| (*k, *v)).eq(expected));
+        }
+
+        let last = versions.last().unwrap();
+        assert!(last.remove(&0).ptr_eq(last));
+        assert!(last.insert(1, 0)[&1] == 0 && last[&1] == 143);
+    }
+
+    #[test]
+    fn persistent_untouched_subtrees_are_shared() {
+        let map: PersistentBstMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        let height = assert_persistent_avl(&map) as usize;
+        let before = persistent_nodes(&map);
+
+        // Only the way down, and what rotations touch, gets copied.
+        let inserted = map.insert(1000, 1000);
+        let copied = persistent_nodes(&inserted).difference(&before).count();
+        assert!(copied <= height + 3);
+        let removed = map.remove(&500);
+        let copied = persistent_nodes(&removed).difference(&before).count();
+        assert!(copied <= 2 * height);
+        assert_persistent_avl(&inserted);
+        assert_persistent_avl(&removed);
+
+        // Dropping a version only frees what nobody else holds.
+        drop(map);
+        assert!(inserted.len() == 1001 && removed.len() == 999);
+        assert!(removed.iter().map(|(k, _)| *k).eq((0..1000).filter(|k| *k != 500)));
+    }
+
+    #[test]
+    fn persistent_lookups() {
+        use std::ops::Bound;
+
+        let map: PersistentBstMap<_, _> = [50, 10, 40, 20, 30].iter().map(|k| (*k, k / 10)).collect();
+        assert!(map.first_key_value() == Some((&10, &1)));
+        assert!(map.last_key_value() == Some((&50, &5)));
+        assert!(map.floor(&25) == Some((&20, &2)) && map.ceiling(&25) == Some((&30, &3)));
+        assert!(map.lower(&10).is_none() && map.higher(&40) == Some((&50, &5)));
+        assert!(map.get_index(3) == Some((&40, &4)) && map.rank(&35) == 3);
+        assert!(map.iter_from_index(3).map(|(k, _)| *k).eq([40, 50]));
+        assert!(map.range(20..=40).rev().map(|(k, _)| *k).eq([40, 30, 20]));
+        assert!(map.range((Bound::Excluded(20), Bound::Excluded(30))).next().is_none());
+        assert!(map.range(10..).len() == 5 && map.iter().len() == 5);
+        assert!(format!("{:?}", map.remove(&30).remove(&40).remove(&50)) == "{10: 1, 20: 2}");
+
+        let strings = PersistentBstMap::new().insert(String::from("ten"), 10);
+        assert!(strings.get("ten") == Some(&10) && strings.remove("ten").is_empty());
+    }
+
+    #[test]
+    fn persistent_shared_across_threads() {
+        let map: PersistentBstMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let handles: Vec<_> = (0..4).map(|t| {
+            let snapshot = map.clone();
+            std::thread::spawn(move || {
+                let mine = snapshot.insert(100 + t, t);
+                assert!(snapshot.len() == 100 && mine.len() == 101);
+                mine.iter().map(|(_, v)| *v).sum::<usize>()
+            })
+        }).collect();
+        for (t, handle) in handles.into_iter().enumerate() {
+            assert!(handle.join().unwrap() == 4950 + t);
+        }
+        assert!(map.len() == 100 && map.get(&100).is_none());
+    }
+
+    #[test]
+    fn persistent_comparator() {
+        let by_len = |a: &String, b: &String| a.len().cmp(&b.len()).then_with(|| a.cmp(b));
+        let mut map = PersistentBstMap::with_comparator(by_len);
+        for word in ["ccc", "a", "bb", "aa", "dddd"] {
+            map = map.insert(word.to_string(), word.len());
+        }
+        assert_persistent_avl(&map);
+        assert!(map.iter().map(|(k, _)| k.as_str()).eq(["a", "aa", "bb", "ccc", "dddd"]));
+        assert!(map.get(&"bb".to_string()) == Some(&2) && map.rank(&"b".to_string()) == 1);
+        assert!(map.floor(&"zz".to_string()).unwrap().0 == "bb");
+        assert!(map.higher(&"zz".to_string()).unwrap().0 == "ccc");
+        let shorter = map.range(..&"ccc".to_string()).map(|(k, _)| k.as_str());
+        assert!(shorter.eq(["a", "aa", "bb"]));
+
+        // Versions keep the comparator.
+        let removed = map.remove(&"aa".to_string()).insert("b".to_string(), 1);
+        assert_persistent_avl(&removed);
+        assert!(removed.iter().map(|(k, _)| k.as_str()).eq(["a", "b", "bb", "ccc", "dddd"]));
+
+        let reversed: PersistentBstMap<_, _, Reverse> = (0..10).map(|i| (i, i)).collect();
+        assert_persistent_avl(&reversed);
+        assert!(reversed.first_key_value() == Some((&9, &9)) && reversed[&3] == 3);
+    }
+
+    #[test]
+    fn persistent_into_iter() {
+        let map: PersistentBstMap<_, _> = (0..100).map(|i| (i, i.to_string())).collect();
+        let snapshot = map.remove(&50);
+
+        // Shared pairs are cloned, so the snapshot is left whole.
+        let mut iter = map.into_iter();
+        assert!(iter.len() == 100);
+        assert!(iter.by_ref().take(10).eq((0..10).map(|i| (i, i.to_string()))));
+        assert!(iter.len() == 90);
+        drop(iter);
+        assert_persistent_avl(&snapshot);
+        assert!(snapshot.len() == 99 && snapshot[&49] == "49");
+
+        // Pairs nobody else holds are moved out.
+        let pairs: Vec<_> = snapshot.into_iter().collect();
+        assert!(pairs.len() == 99 && pairs[50] == (51, "51".to_string()));
+
+        let value = Arc::new(());
+        let map = PersistentBstMap::new().insert(1, value.clone()).insert(2, value.clone());
+        let other = map.insert(3, value.clone());
+        assert!(map.into_iter().count() == 2 && Arc::strong_count(&value) == 4);
+        assert!(other.into_iter().count() == 3 && Arc::strong_count(&value) == 1);
+    }
 }
@@ -1,351 +1,826 @@
-use std::{fmt::{Display, Debug}, cmp::Ordering};
+use std::{fmt::Debug, cmp::Ordering, borrow::Borrow};
+use std::marker::PhantomData;
 use super::action::*;
+use super::compare::Compare;
+use super::allocator::{self, Allocator, NodeBox};
+use super::pool::NodePool;
 
-// Used to simplify remove_first and remove_last functions. 
+// Used to simplify the pop_first, pop_last, first_entry and last_entry functions.
 pub enum NodePosition {
     First,
     Last,
 }
 
-pub type NodeLink<T, V> = Option<Box<Node<T, V>>>;
-// Internal Node used by BstMap to structure binary tree.  
+// Used to simplify the floor, ceiling, lower and higher functions.
+pub enum Neighbor {
+    // Largest key <= the given key
+    Floor,
+    // Smallest key >= the given key
+    Ceiling,
+    // Largest key < the given key
+    Lower,
+    // Smallest key > the given key
+    Higher,
+}
+
+impl Neighbor {
+    // Does a Node that compares to the key with ordering qualify?
+    // If it does, any better match is further towards the key,
+    // so the descent carries on in the direction of the key.
+    pub fn accepts(&self, ordering: Ordering) -> bool {
+        match self {
+            Neighbor::Floor => ordering != Ordering::Greater,
+            Neighbor::Ceiling => ordering != Ordering::Less,
+            Neighbor::Lower => ordering == Ordering::Less,
+            Neighbor::Higher => ordering == Ordering::Greater,
+        }
+    }
+
+    // Which way to go after a Node qualifies.
+    pub fn towards_key(&self) -> Ordering {
+        match self {
+            Neighbor::Floor | Neighbor::Lower => Ordering::Greater,
+            Neighbor::Ceiling | Neighbor::Higher => Ordering::Less,
+        }
+    }
+}
+
+pub type NodeLink<T, V, A> = Option<NodeBox<T, V, A>>;
+// Raw pointer to a NodeLink somewhere inside the tree.
+pub type LinkPtr<T, V, A> = *mut NodeLink<T, V, A>;
+// What Node::split returns: the smaller keys, the matching Node, the larger keys.
+pub type Split<T, V, A> = (NodeLink<T, V, A>, NodeLink<T, V, A>, NodeLink<T, V, A>);
+
+// Internal Node used by BstMap to structure binary tree.
+//
+// The tree is kept AVL balanced: the heights of the left and right
+// subtrees of every Node differ by at most one. Each Node caches its own
+// height so the balance factor can be checked without walking the tree.
+//
+// Each Node also caches the number of Nodes in its subtree (itself
+// included), which makes finding the n-th key or the rank of a key
+// O(log n). Both are kept up to date by update, which rebalancing
+// calls on every Node it passes.
+//
+// Keys are never compared directly. Every function that descends by key
+// takes the map's comparator as cmp, see compare.rs.
+//
+// Nothing in here recurses. Lookups loop down the tree, traversals keep
+// an explicit stack, and anything that changes the shape of the tree
+// records a Path on the way down so it can rebalance on the way back up.
 #[derive(Debug)]
-pub struct Node<T: Ord + Debug, V: Debug> {
+pub struct Node<T, V, A: Allocator + Clone> {
     pub key: T,
     pub value: V,
-    pub left: NodeLink<T, V>,
-    pub right: NodeLink<T, V>,
+    pub left: NodeLink<T, V, A>,
+    pub right: NodeLink<T, V, A>,
+    pub height: u8,
+    pub size: usize,
+    // Without the allocator_api feature, NodeBox doesn't hold an A.
+    pub marker: PhantomData<A>,
 }
 
+// The links followed while descending from the head, top first.
+//
+// Rebalancing after an insert or remove has to visit every Node above
+// the change, deepest first. Instead of recursing, the descent records
+// which way it turned at each Node it passed through, and retrace follows
+// those turns back down from root to find every link again. The turns
+// stay valid for as long as nothing above the change is touched: a
+// rotation only changes which Box a link holds, never where the link
+// itself lives.
+//
+// Turns are kept as bits, so a Path is small and descending never
+// allocates. An AVL tree of height h holds at least fib(h + 2) - 1 Nodes,
+// which puts any tree that fits in memory well below MAX_HEIGHT levels.
+pub struct Path<T, V, A: Allocator + Clone> {
+    root: LinkPtr<T, V, A>,
+    // Bit i is set if the descent went left below the i-th link.
+    turns: u128,
+    len: usize,
+}
 
-impl<'a, T: Ord + Debug, V: Debug> Node<T, V> {
-    // Returns a new Node with no children. 
-    pub fn new(key: T, value: V) -> Self {
-        Self {
-            key,
-            value,
-            left: None,
-            right: None,
-        }
+const MAX_HEIGHT: usize = 128;
+// Every link a Path passes through, top first.
+type Links<T, V, A> = [LinkPtr<T, V, A>; MAX_HEIGHT];
+
+impl<T, V, A: Allocator + Clone> Path<T, V, A> {
+    fn new(root: LinkPtr<T, V, A>) -> Self {
+        Self { root, turns: 0, len: 0 }
+    }
+
+    // Records the next link, and which way the descent turned below it.
+    fn push(&mut self, left: bool) {
+        self.turns |= (left as u128) << self.len;
+        self.len += 1;
     }
 
-    // Fills passed vector with every key/value pair as owned values,  
-    // consuming the BstMap.
-    pub fn fill_owned_vec(self, vec: &mut Vec<(T, V)>) {
-        if let Some(node) = self.left {
-            node.fill_owned_vec(vec);
+    // Follows the turns back down from root. Returns every recorded
+    // link, top first, and the link the Path led to.
+    unsafe fn links(&self) -> (Links<T, V, A>, LinkPtr<T, V, A>) {
+        let mut links: Links<T, V, A> = [std::ptr::null_mut(); MAX_HEIGHT];
+        let mut link = self.root;
+        for (i, slot) in links[..self.len].iter_mut().enumerate() {
+            *slot = link;
+            let node = (*link).as_mut().unwrap();
+            link = if self.turns >> i & 1 == 1 { &mut node.left } else { &mut node.right };
         }
+        (links, link)
+    }
 
-        vec.push((self.key, self.value));
+    // Rebalances every recorded link, deepest first.
+    //
+    // Safety: every recorded link must still hold the Node it held when
+    // recorded, and nothing else may be borrowing the tree.
+    pub unsafe fn retrace(self) {
+        let (links, _) = self.links();
+        for &link in links[..self.len].iter().rev() {
+            if let Some(node) = &mut *link {
+                node.rebalance();
+            }
+        }
+    }
 
-        if let Some(node) = self.right {
-            node.fill_owned_vec(vec);
+    // Same as retrace, but also follows the Node held by the link the
+    // Path led to, and returns the link that ends up holding it.
+    //
+    // Rebalancing a link borrows its Node and every Node it rotates,
+    // which leaves any pointer taken into them before unusable. Those
+    // Nodes all end up within the top three levels under the link, so
+    // whenever the Node at the end could have been affected, it is looked
+    // up again by address among the top four. Anything deeper was left
+    // alone, so this stays a single pass up the Path.
+    //
+    // Safety: same as retrace, and the link the Path led to must hold a Node.
+    pub unsafe fn retrace_end(self) -> LinkPtr<T, V, A> {
+        let (links, mut end) = self.links();
+        let target: *const Node<T, V, A> = &**(*end).as_ref().unwrap();
+        for (i, &link) in links[..self.len].iter().enumerate().rev() {
+            if let Some(node) = &mut *link {
+                // The deepest link holds the parent of target.
+                if node.rebalance() || i + 1 == self.len {
+                    end = Path::find(link, target).unwrap_or(end);
+                }
+            }
         }
+        end
     }
 
-    // Fills passed vector with every key/value pair as borrowed values.
-    pub fn fill_ref_vec(&'a self, vec: &mut Vec<(&'a T, &'a V)>) {
-        if let Some(ref node) = self.left {
-            node.fill_ref_vec(vec);
+    // Looks for the link holding target among the top four levels under link.
+    unsafe fn find(link: LinkPtr<T, V, A>, target: *const Node<T, V, A>) -> Option<LinkPtr<T, V, A>> {
+        // In level order: the children of links[i] are links[2i + 1] and links[2i + 2].
+        let mut links: [LinkPtr<T, V, A>; 15] = [std::ptr::null_mut(); 15];
+        links[0] = link;
+        for i in 0..links.len() {
+            let Some(node) = links[i].as_mut().and_then(Option::as_mut) else { continue };
+            if std::ptr::eq(&**node, target) { return Some(links[i]) }
+            if 2 * i + 2 < links.len() {
+                links[2 * i + 1] = &mut node.left;
+                links[2 * i + 2] = &mut node.right;
+            }
         }
+        None
+    }
+}
 
-        vec.push((&self.key, &self.value));
 
-        if let Some(ref node) = self.right {
-            node.fill_ref_vec(vec);
+impl<T, V, A: Allocator + Clone> Node<T, V, A> {
+    // Returns a new Node with no children.
+    pub fn new(key: T, value: V) -> Self {
+        Self {
+            key,
+            value,
+            left: None,
+            right: None,
+            height: 1,
+            size: 1,
+            marker: PhantomData,
         }
     }
 
-    // Fills passed vector with every key/value pair as mutable values.
-    pub fn fill_mut_vec(&'a mut self, vec: &mut Vec<(&'a T, &'a mut V)>) {
-        if let Some(ref mut node) = self.left {
-            node.fill_mut_vec(vec);
+    // Walks down from root until it finds the Node matching key, or the
+    // empty link where that Node would be inserted.
+    //
+    // Returns the Path to (but not including) that link, and the link.
+    pub fn seek<Q, C>(root: &mut NodeLink<T, V, A>, key: &Q, cmp: &C)
+            -> (Path<T, V, A>, LinkPtr<T, V, A>)
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut link: LinkPtr<T, V, A> = root;
+        let mut path = Path::new(link);
+        // Safety: link always points either at root or at a child link
+        // of a Node reached through root, which we borrow mutably.
+        unsafe {
+            while let Some(node) = &mut *link {
+                let left = match cmp.compare(key, node.key.borrow()) {
+                    Ordering::Greater => false,
+                    Ordering::Less => true,
+                    Ordering::Equal => break,
+                };
+                path.push(left);
+                link = if left { &mut node.left } else { &mut node.right };
+            }
         }
+        (path, link)
+    }
 
-        vec.push((&self.key, &mut self.value));
+    // Same as seek, but walks to the first or last Node instead of
+    // following a key. The returned link is only empty if root is.
+    pub fn seek_position(root: &mut NodeLink<T, V, A>, pos: NodePosition) -> (Path<T, V, A>, LinkPtr<T, V, A>) {
+        let mut link: LinkPtr<T, V, A> = root;
+        let mut path = Path::new(link);
+        // Safety: see seek.
+        unsafe {
+            while let Some(node) = &mut *link {
+                // Are we looking left or right?
+                let next: LinkPtr<T, V, A> = match pos {
+                    NodePosition::First => &mut node.left,
+                    NodePosition::Last => &mut node.right,
+                };
+                // No further to go, this is the one.
+                if (*next).is_none() { break }
+                path.push(matches!(pos, NodePosition::First));
+                link = next;
+            }
+        }
+        (path, link)
+    }
 
-        if let Some(ref mut node) = self.right {
-            node.fill_mut_vec(vec);
+    // Same as seek, but walks to the Node at index in sorted order.
+    // The returned link is only empty if index is out of bounds.
+    pub fn seek_index(root: &mut NodeLink<T, V, A>, mut index: usize) -> (Path<T, V, A>, LinkPtr<T, V, A>) {
+        let mut link: LinkPtr<T, V, A> = root;
+        let mut path = Path::new(link);
+        // Safety: see seek.
+        unsafe {
+            while let Some(node) = &mut *link {
+                let left_size = Node::link_size(&node.left);
+                let left = match index.cmp(&left_size) {
+                    Ordering::Less => true,
+                    Ordering::Equal => break,
+                    // Skip past the left subtree and ourselves.
+                    Ordering::Greater => {
+                        index -= left_size + 1;
+                        false
+                    }
+                };
+                path.push(left);
+                link = if left { &mut node.left } else { &mut node.right };
+            }
         }
+        (path, link)
     }
 
-    // Recurse function which traverses the tree until it finds the
-    // proper location to insert key/value pair.
+    // Traverses the tree until it finds the proper location to insert
+    // key/value pair.
     //
-    // If key already exists, old value is clobbered. 
-    pub fn insert(&mut self, key: T, value: V) -> InsertAction {
-
-        let node_link: &mut NodeLink<T, V> = match key.cmp(&self.key) {
-            Ordering::Greater => &mut self.right,
-            Ordering::Less => &mut self.left,
-            // We match the insert key. Clobber the old value
-            // and pass a None action since no Node was added.
-            Ordering::Equal => {
-                self.value = value;
-                return InsertAction::None;
+    // If key already exists, old value is clobbered.
+    pub fn insert<C>(root: &mut NodeLink<T, V, A>, pool: &mut NodePool<T, V, A>,
+                     key: T, value: V, cmp: &C) -> InsertAction<V>
+            where C: Compare<T> {
+
+        let (path, link) = Node::seek(root, &key, cmp);
+        // Safety: path and link were just recorded from root.
+        unsafe {
+            match &mut *link {
+                // We match the insert key. Clobber the old value
+                // and pass it along since no Node was added.
+                Some(node) => InsertAction::Replaced(std::mem::replace(&mut node.value, value)),
+                None => {
+                    *link = Some(pool.alloc(Node::new(key, value)));
+                    path.retrace();
+                    InsertAction::Increment
+                }
             }
-        };
-
-        // Either call recursively or insert child
-        if let Some(node) = node_link {
-            node.insert(key, value)
-        } else {
-            *node_link = Some(Box::new(Node::new(key, value)));
-            InsertAction::Increment
         }
     }
 
-    // Recurse function which traverses the tree until it finds the
-    // proper location to insert key/value pair.
+    // Traverses the tree until it finds the proper location to insert
+    // key/value pair.
     //
     // If key already exists, func is called to update the existing value
     // instead of clobbering.
-    pub fn insert_or<F>(&mut self, key: T, value: V, mut func: F) -> InsertAction
-            where F: FnMut(&mut V) {
-
-        let node_link: &mut NodeLink<T, V> = match key.cmp(&self.key) {
-            Ordering::Greater => &mut self.right,
-            Ordering::Less => &mut self.left,
-            // We match the insert key. Call the provided
-            // update function and pass a None action since no
-            // Node was added.
-            Ordering::Equal => {
-                func(&mut self.value);
-                return InsertAction::None;
+    pub fn insert_or<C, F>(root: &mut NodeLink<T, V, A>, pool: &mut NodePool<T, V, A>,
+                           key: T, value: V, cmp: &C, mut func: F) -> InsertAction<V>
+            where C: Compare<T>, F: FnMut(&mut V) {
+
+        let (path, link) = Node::seek(root, &key, cmp);
+        // Safety: path and link were just recorded from root.
+        unsafe {
+            match &mut *link {
+                // We match the insert key. Call the provided
+                // update function and pass a None action since no
+                // Node was added.
+                Some(node) => {
+                    func(&mut node.value);
+                    InsertAction::None
+                }
+                None => {
+                    *link = Some(pool.alloc(Node::new(key, value)));
+                    path.retrace();
+                    InsertAction::Increment
+                }
             }
-        };
-
-        // Either call recursively or insert child
-        if let Some(node) = node_link {
-            node.insert_or(key, value, func)
-        } else {
-            *node_link = Some(Box::new(Node::new(key, value)));
-            InsertAction::Increment
         }
     }
 
-    // Returns reference to value refferred to by key. Returns None
-    // if key is not found. 
-    pub fn get(&self, key: T) -> Option<&V> {
+    // Swaps the key stored in the Node matching key for key itself,
+    // passing the old one along. Returns None if there is no match.
+    pub fn replace_key<C>(root: &mut NodeLink<T, V, A>, key: T, cmp: &C) -> Option<T>
+            where C: Compare<T> {
 
-        let node_link: &NodeLink<T, V> = match key.cmp(&self.key) {
-            Ordering::Greater => &self.right,
-            Ordering::Less => &self.left,
-            // Return a reference to our value
-            Ordering::Equal => return Some(&self.value),
-        };
+        let (_, link) = Node::seek(root, &key, cmp);
+        // Safety: link was just recorded from root. The keys are
+        // equal, so the tree stays in order.
+        unsafe { (*link).as_mut().map(|node| std::mem::replace(&mut node.key, key)) }
+    }
 
-        if let Some(node) = node_link {
-            node.get(key)
-        } else {
-            None
+    // Returns reference to value refferred to by key. Returns None
+    // if key is not found.
+    pub fn get<Q, C>(&self, key: &Q, cmp: &C) -> Option<&V>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut node = self;
+        loop {
+            let node_link: &NodeLink<T, V, A> = match cmp.compare(key, node.key.borrow()) {
+                Ordering::Greater => &node.right,
+                Ordering::Less => &node.left,
+                // Return a reference to our value
+                Ordering::Equal => return Some(&node.value),
+            };
+            node = node_link.as_deref()?;
         }
-        
     }
 
     // Returns mutable reference to value refferred to by key.
-    // Returns None if key is not found. 
-    pub fn get_mut(&mut self, key: T) -> Option<&mut V> {
-
-        let node_link: &mut NodeLink<T, V> = match key.cmp(&self.key) {
-            Ordering::Greater => &mut self.right,
-            Ordering::Less => &mut self.right,
-            Ordering::Equal => return Some(&mut self.value),
-        };
-
-        if let Some(node) = node_link {
-            node.get_mut(key)
-        } else { None }
+    // Returns None if key is not found.
+    pub fn get_mut<Q, C>(&mut self, key: &Q, cmp: &C) -> Option<&mut V>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut node = self;
+        loop {
+            let node_link: &mut NodeLink<T, V, A> = match cmp.compare(key, node.key.borrow()) {
+                Ordering::Greater => &mut node.right,
+                Ordering::Less => &mut node.left,
+                Ordering::Equal => return Some(&mut node.value),
+            };
+            node = node_link.as_deref_mut()?;
+        }
     }
 
     // Returns the logical "first" key/value pair. (farthest left)
-    // Recursively calls to the left until it reaches the end. 
     pub fn first_key_value(&self) -> Option<(&T, &V)> {
-        if let Some(ref node) = self.left {
-            node.first_key_value()
-        } else {
-            Some((&self.key, &self.value))
+        let mut node = self;
+        while let Some(ref left) = node.left {
+            node = left;
         }
+        Some((&node.key, &node.value))
     }
 
     // Returns the logical "last" key/value pair. (farthest right)
-    // Recursively calls to the right until it reaches the end. 
     pub fn last_key_value(&self) -> Option<(&T, &V)> {
-        if let Some(ref node) = self.right {
-            node.last_key_value()
-        } else {
-            Some((&self.key, &self.value))
+        let mut node = self;
+        while let Some(ref right) = node.right {
+            node = right;
         }
+        Some((&node.key, &node.value))
     }
 
-    // Recursively seeks a Node to remove. If desired Node is reached,
-    // a replacement strategy is chosen based on the number of children 
-    // the Node has. 
-    //
-    // The only complicated scenario is if the Node has two children,
-    // where the chosen strategy is to find the Node's inline successor
-    // to take its place. 
-    pub fn remove(&mut self, key: T) -> RemoveAction<T, V> {
-
-        let node_link: &mut NodeLink<T, V> = match key.cmp(&self.key) {
-            Ordering::Greater => &mut self.right,
-            Ordering::Less => &mut self.left,
-            // That's us! Return the node that is going to replace us.
-            Ordering::Equal => {
-                return RemoveAction::UpdateNode(self.replacement_node());
-            }
-        };
-
-        if let Some(node) = node_link {
-            let action = node.remove(key);
-            match action {
-                // Just pass action along
-                // Nothing to do
-                RemoveAction::Return(_) => action,
-                // Grab the value out of the old node
-                // Replace child with new node
-                // Pass along value from old node
-                RemoveAction::UpdateNode(new_node) => {
-                    let value = node_link.take().unwrap().value;
-                    *node_link = new_node;
-                    RemoveAction::Return(Some(value))
+    // Returns the nearest key/value pair to key on the side picked
+    // by neighbor, in a single descent.
+    pub fn neighbor<Q, C>(&self, key: &Q, neighbor: Neighbor, cmp: &C) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut best = None;
+        let mut link = Some(self);
+        while let Some(node) = link {
+            let ordering = cmp.compare(node.key.borrow(), key);
+            // Either a candidate, or we've overshot and need to turn around.
+            let direction = if neighbor.accepts(ordering) {
+                best = Some((&node.key, &node.value));
+                neighbor.towards_key()
+            } else {
+                neighbor.towards_key().reverse()
+            };
+            link = match direction {
+                Ordering::Greater => node.right.as_deref(),
+                _ => node.left.as_deref(),
+            };
+        }
+        best
+    }
+
+    // Same as neighbor, with a mutable value.
+    pub fn neighbor_mut<Q, C>(&mut self, key: &Q, neighbor: Neighbor, cmp: &C) -> Option<(&T, &mut V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut best = None;
+        let mut link = Some(self);
+        while let Some(node) = link {
+            // Split the Node up so the value can be held on to
+            // while we carry on down one of the children.
+            let Node { key: node_key, value, left, right, .. } = node;
+            let ordering = cmp.compare((*node_key).borrow(), key);
+            let direction = if neighbor.accepts(ordering) {
+                best = Some((&*node_key, value));
+                neighbor.towards_key()
+            } else {
+                neighbor.towards_key().reverse()
+            };
+            link = match direction {
+                Ordering::Greater => right.as_deref_mut(),
+                _ => left.as_deref_mut(),
+            };
+        }
+        best
+    }
+
+    // Returns the key/value pair at index in sorted order,
+    // counting from 0. Returns None if index is out of bounds.
+    pub fn get_index(&self, mut index: usize) -> Option<(&T, &V)> {
+        let mut node = self;
+        loop {
+            let left_size = Node::link_size(&node.left);
+            let node_link: &NodeLink<T, V, A> = match index.cmp(&left_size) {
+                Ordering::Less => &node.left,
+                Ordering::Equal => return Some((&node.key, &node.value)),
+                // Skip past the left subtree and ourselves.
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    &node.right
                 }
+            };
+            node = node_link.as_deref()?;
+        }
+    }
+
+    // Returns the number of keys strictly less than key.
+    pub fn rank<Q, C>(&self, key: &Q, cmp: &C) -> usize
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut rank = 0;
+        let mut link = Some(self);
+        while let Some(node) = link {
+            link = match cmp.compare(key, node.key.borrow()) {
+                // Everything in our left subtree and ourselves is smaller.
+                Ordering::Greater => {
+                    rank += Node::link_size(&node.left) + 1;
+                    node.right.as_deref()
+                }
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Equal => return rank + Node::link_size(&node.left),
+            };
+        }
+        rank
+    }
+
+    // Seeks a Node to remove and unlinks it.
+    pub fn remove<Q, C>(root: &mut NodeLink<T, V, A>, key: &Q, cmp: &C) -> RemoveAction<NodeBox<T, V, A>>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let (path, link) = Node::seek(root, key, cmp);
+        // Safety: path and link were just recorded from root.
+        unsafe {
+            if (*link).is_some() {
+                RemoveAction::Removed(Node::unlink(path, link))
             }
-        } else {
             // Otherwise no match is possible
-            RemoveAction::Return(None)
+            else { RemoveAction::None }
         }
     }
 
-    // Seeks a Node to replace the current one. 
-    fn replacement_node(&mut self) -> NodeLink<T, V> {
-        match self.has_children() {
-            // I am a leaf. Whoosh.
-            // Replace my NodeLink with None
-            (false, false) => None,
-            // If I have only left or right child,
-            // replace my Nodelink with one of them
-            (true, false) => self.left.take(),
-            (false, true) => self.right.take(),
+    // Remove a node at NodePosition::First or NodePosition::Last.
+    pub fn remove_position(root: &mut NodeLink<T, V, A>, pos: NodePosition) -> RemoveAction<NodeBox<T, V, A>> {
+        let (path, link) = Node::seek_position(root, pos);
+        // Safety: path and link were just recorded from root.
+        unsafe {
+            if (*link).is_some() {
+                RemoveAction::Removed(Node::unlink(path, link))
+            }
+            // Can't remove anything from an empty tree.
+            else { RemoveAction::None }
+        }
+    }
+
+    // Remove the Node at index in sorted order.
+    pub fn remove_index(root: &mut NodeLink<T, V, A>, index: usize) -> RemoveAction<NodeBox<T, V, A>> {
+        let (path, link) = Node::seek_index(root, index);
+        // Safety: path and link were just recorded from root.
+        unsafe {
+            if (*link).is_some() {
+                RemoveAction::Removed(Node::unlink(path, link))
+            }
+            // Index is past the end.
+            else { RemoveAction::None }
+        }
+    }
+
+    // Unlinks the Node held by link, choosing a replacement strategy
+    // based on the number of children the Node has, then rebalances
+    // everything above it. Returns the unlinked Node.
+    //
+    // The only complicated scenario is if the Node has two children,
+    // where the chosen strategy is to find the Node's inline successor
+    // to take its place. Rather than moving Nodes around, the payloads
+    // are swapped and the successor's Node is unlinked instead. This way
+    // every Node the Path passes through stays where it was.
+    //
+    // Safety: link must hold a Node, and path must be the Path to link
+    // as returned by one of the seek functions.
+    pub unsafe fn unlink(mut path: Path<T, V, A>, link: LinkPtr<T, V, A>) -> NodeBox<T, V, A> {
+        let node: *mut Node<T, V, A> = &mut **(*link).as_mut().unwrap();
+        match ((*node).left.is_some(), (*node).right.is_some()) {
             // I have two children and I have to
             // replace myself with my nearest successor
             (true, true) => {
-                // Pick up our nodes since they no longer need to be owned by self
-                let left = self.left.take();
-                let mut right = self.right.take().unwrap();
-
-                // If our right node has no left node, it is the successor
-                // Move self left node to successor left node.
-                // Then return successor node to replace us. 
-                if right.is_successor() {
-                    right.left = left;
-                    Some(right)
-                } 
-
-                // Otherwise we need to go looking for the successor
-                else {
-                    // First call to get_successor is to the right child.
-                    // All further recursive calls will be to the left child.
-                    let mut replacement = right.get_successor().unwrap();
-                    // We move our children over to our replacement
-                    // and return the replacement
-                    replacement.left = left;
-                    replacement.right = Some(right);
-                    Some(replacement)
+                path.push(false);
+
+                // The successor is the leftmost Node of our right subtree.
+                let mut successor_link: LinkPtr<T, V, A> = &mut (*node).right;
+                while let Some(successor) = &mut *successor_link {
+                    if successor.left.is_none() { break }
+                    path.push(true);
+                    successor_link = &mut successor.left;
                 }
+
+                // The successor has no left child, so its right child
+                // (if any) simply takes its place.
+                let mut successor = (*successor_link).take().unwrap();
+                *successor_link = successor.right.take();
+
+                std::mem::swap(&mut (*node).key, &mut successor.key);
+                std::mem::swap(&mut (*node).value, &mut successor.value);
+                path.retrace();
+                successor
+            }
+            // I am a leaf, or I have only a left or right child.
+            // Replace my NodeLink with the child, or None.
+            _ => {
+                let mut removed = (*link).take().unwrap();
+                *link = removed.left.take().or_else(|| removed.right.take());
+                path.retrace();
+                removed
             }
         }
     }
 
-    // When looking for the successor, the first node we find that
-    // has no left child node is the successor.
-    fn is_successor(&self) -> bool { !self.left.is_some() }
-
-    // I am not the successor, but is my left node pointing
-    // to the successor?
+    // Joins two trees around mid, where every key in left is smaller
+    // than mid's key and every key in right is larger. mid must have
+    // no children. Runs in O(difference in height).
     //
-    // Return successor if so, otherwise call recursively on
-    // left node
-    fn get_successor(&mut self) -> NodeLink<T, V> {
-        // Safe to unwrap here
-        // None is impossible (I hope)
-        let left = self.left.as_mut().unwrap();
-        if left.is_successor() {
-            // Take the successor node, and assign self.left to
-            // successor's right node if there is one.
-            let mut successor = self.left.take().unwrap();
-            if successor.right.is_some() { self.left = successor.right.take(); }
-
-            // Wrap successor back up
-            Some(successor)
-        } 
+    // mid is hung off the spine of the taller tree, at the first Node
+    // whose height is within one of the shorter tree, taking that Node's
+    // place and adopting it and the shorter tree as children. Everything
+    // above it on the spine is then rebalanced.
+    pub fn join(left: NodeLink<T, V, A>, mut mid: NodeBox<T, V, A>, right: NodeLink<T, V, A>)
+            -> NodeBox<T, V, A> {
+
+        let left_height = Node::link_height(&left);
+        let right_height = Node::link_height(&right);
+
+        if left_height > right_height + 1 {
+            let mut root = left;
+            let mut link: LinkPtr<T, V, A> = &mut root;
+            let mut path = Path::new(link);
+            // Safety: link only ever points into root, which we own.
+            unsafe {
+                while Node::link_height(&*link) > right_height + 1 {
+                    path.push(false);
+                    link = &mut (*link).as_mut().unwrap().right;
+                }
+                mid.left = (*link).take();
+                mid.right = right;
+                mid.update();
+                *link = Some(mid);
+                path.retrace();
+            }
+            root.unwrap()
+        }
+        else if right_height > left_height + 1 {
+            let mut root = right;
+            let mut link: LinkPtr<T, V, A> = &mut root;
+            let mut path = Path::new(link);
+            // Safety: see above.
+            unsafe {
+                while Node::link_height(&*link) > left_height + 1 {
+                    path.push(true);
+                    link = &mut (*link).as_mut().unwrap().left;
+                }
+                mid.left = left;
+                mid.right = (*link).take();
+                mid.update();
+                *link = Some(mid);
+                path.retrace();
+            }
+            root.unwrap()
+        }
+        // Close enough in height already.
         else {
-            left.get_successor()
+            mid.left = left;
+            mid.right = right;
+            mid.update();
+            mid
         }
     }
 
-    // Remove a node at NodePosition::First or NodePosition::Last.
-    pub fn remove_position(&mut self, pos: NodePosition) -> RemoveAction<T, V> {
-
-        // Are we looking left or right?
-        let node_link: &mut NodeLink<T, V> = match pos {
-            NodePosition::First => &mut self.left,
-            NodePosition::Last => &mut self.right, 
-        };
-
-        // If there is a node there...
-        if let Some(node) = node_link {
-            let action = node.remove_position(pos);
-            match action {
-                RemoveAction::Return(_) => action,
-                RemoveAction::UpdateNode(new_node) => {
-                    let value = node_link.take().unwrap().value;
-                    *node_link = new_node;
-                    RemoveAction::Return(Some(value))
+    // Same as join, without a Node to join around. The first Node of
+    // right is unlinked and used instead.
+    pub fn join2(left: NodeLink<T, V, A>, right: NodeLink<T, V, A>) -> NodeLink<T, V, A> {
+        let mut right = right;
+        let (path, link) = Node::seek_position(&mut right, NodePosition::First);
+        // Safety: path and link were just recorded from right.
+        if unsafe { (*link).is_none() } { return left }
+        let mid = unsafe { Node::unlink(path, link) };
+        Some(Node::join(left, mid, right))
+    }
+
+    // Splits the tree under root into the keys smaller than key and the
+    // keys larger than key, plus the Node matching key if there is one.
+    // The matching Node is returned without children. Runs in O(log n).
+    //
+    // On the way down every Node is cut off from the child we follow,
+    // leaving it with the subtree on the side away from key. On the way
+    // back up, those pieces are joined back together on each side.
+    pub fn split<Q, C>(root: NodeLink<T, V, A>, key: &Q, cmp: &C) -> Split<T, V, A>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut smaller = Vec::new();
+        let mut larger = Vec::new();
+        let mut found = None;
+        let mut left = None;
+        let mut right = None;
+
+        let mut link = root;
+        while let Some(mut node) = link {
+            link = match cmp.compare(key, node.key.borrow()) {
+                Ordering::Greater => {
+                    let next = node.right.take();
+                    smaller.push(node);
+                    next
+                }
+                Ordering::Less => {
+                    let next = node.left.take();
+                    larger.push(node);
+                    next
                 }
+                Ordering::Equal => {
+                    left = node.left.take();
+                    right = node.right.take();
+                    found = Some(node);
+                    None
+                }
+            };
+        }
+
+        // Deepest pieces first, since they're the closest to key.
+        while let Some(mut node) = smaller.pop() {
+            let subtree = node.left.take();
+            left = Some(Node::join(subtree, node, left));
+        }
+        while let Some(mut node) = larger.pop() {
+            let subtree = node.right.take();
+            right = Some(Node::join(right, node, subtree));
+        }
+        (left, found, right)
+    }
+
+    // Drops every Node under link without recursing or allocating.
+    //
+    // Left children are rotated up until the top Node has none, at which
+    // point it can be dropped on its own and its right child takes over.
+    pub fn drop_tree(link: NodeLink<T, V, A>) {
+        let mut link = link;
+        while let Some(mut node) = link {
+            if let Some(mut left) = node.left.take() {
+                node.left = left.right.take();
+                left.right = Some(node);
+                link = Some(left);
+            } else {
+                link = node.right.take();
             }
-        } 
-        // Otherwise its us! Find and pass on our replacement.
-        else { RemoveAction::UpdateNode(self.replacement_node()) }
+        }
     }
 
-    fn has_children(&self) -> (bool, bool) {
-        (self.left.is_some(), self.right.is_some())
+    // Builds a perfectly balanced tree out of pairs, which must be
+    // in strictly ascending key order. Takes O(n).
+    pub fn from_sorted(pairs: Vec<(T, V)>, alloc: &A) -> NodeLink<T, V, A> {
+        let len = pairs.len();
+        Node::build(&mut pairs.into_iter(), len, alloc)
+    }
+
+    // Builds a tree out of the next len pairs, the middle one being
+    // the root. Both halves differ in size by at most one, so this
+    // only recurses as deep as the tree it builds, O(log n).
+    fn build<I>(pairs: &mut I, len: usize, alloc: &A) -> NodeLink<T, V, A>
+            where I: Iterator<Item = (T, V)> {
+
+        if len == 0 { return None }
+        let left_len = (len - 1) / 2;
+        let left = Node::build(pairs, left_len, alloc);
+        let (key, value) = pairs.next().unwrap();
+        let mut node = allocator::boxed(Node::new(key, value), alloc);
+        node.left = left;
+        node.right = Node::build(pairs, len - 1 - left_len, alloc);
+        node.update();
+        Some(node)
+    }
+
+    // Copies every Node under link without recursing. The copy has
+    // the same shape, so cached heights and sizes carry over as is.
+    pub fn clone_tree(link: &NodeLink<T, V, A>, alloc: &A) -> NodeLink<T, V, A>
+            where T: Clone, V: Clone {
+
+        let mut head = None;
+        // (Node to copy, empty link the copy goes into)
+        let mut stack = Vec::new();
+        if let Some(node) = link.as_deref() { stack.push((node, &mut head)); }
+        while let Some((node, copy)) = stack.pop() {
+            let copy = copy.insert(allocator::boxed(Node {
+                key: node.key.clone(),
+                value: node.value.clone(),
+                left: None,
+                right: None,
+                height: node.height,
+                size: node.size,
+                marker: PhantomData,
+            }, alloc));
+            let Node { left, right, .. } = &mut **copy;
+            if let Some(node) = node.left.as_deref() { stack.push((node, left)); }
+            if let Some(node) = node.right.as_deref() { stack.push((node, right)); }
+        }
+        head
+    }
+
+    // Height of the subtree under link. None has a height of 0.
+    pub fn link_height(link: &NodeLink<T, V, A>) -> u8 {
+        link.as_ref().map_or(0, |node| node.height)
+    }
+
+    // Number of Nodes under link. None has a size of 0.
+    pub fn link_size(link: &NodeLink<T, V, A>) -> usize {
+        link.as_ref().map_or(0, |node| node.size)
+    }
+
+    // Recomputes our height and size from our children. Must be called
+    // whenever a child link changes.
+    fn update(&mut self) {
+        self.height = 1 + Node::link_height(&self.left).max(Node::link_height(&self.right));
+        self.size = 1 + Node::link_size(&self.left) + Node::link_size(&self.right);
+    }
+
+    // Left height minus right height. Anything outside of -1..=1
+    // needs a rotation.
+    pub fn balance_factor(&self) -> i16 {
+        Node::link_height(&self.left) as i16 - Node::link_height(&self.right) as i16
+    }
+
+    // Restores the AVL invariant for this Node, assuming both subtrees
+    // are already balanced and differ in height by at most two.
+    //
+    // Rotations replace the Node a Box points to, which is why this works
+    // on the Box rather than on the Node. Returns whether it rotated.
+    pub fn rebalance(self: &mut NodeBox<T, V, A>) -> bool {
+        self.update();
+        match self.balance_factor() {
+            // Left heavy
+            2 => {
+                // Left-Right case: straighten out the left child first
+                if self.left.as_ref().unwrap().balance_factor() < 0 {
+                    self.left.as_mut().unwrap().rotate_left();
+                }
+                self.rotate_right();
+                true
+            }
+            // Right heavy
+            -2 => {
+                // Right-Left case: straighten out the right child first
+                if self.right.as_ref().unwrap().balance_factor() > 0 {
+                    self.right.as_mut().unwrap().rotate_right();
+                }
+                self.rotate_left();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Our right child takes our place and we become its left child.
+    fn rotate_left(self: &mut NodeBox<T, V, A>) {
+        let mut right = self.right.take().unwrap();
+        self.right = right.left.take();
+        self.update();
+        std::mem::swap(self, &mut right);
+        self.left = Some(right);
+        self.update();
     }
-}
 
-// trait impl
-
-impl<T: Ord + Debug, V: Debug> Display for Node<T, V> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let key_left = match &self.left {
-            Some(node) => format!("{:?}", node.key),
-            None => "None".to_string(),
-        };
-        let key_right = match &self.right {
-            Some(node) => format!("{:?}", node.key),
-            None => "None".to_string(),
-        };
-        let node_left = match &self.left {
-            Some(node) => format!("{}", node),
-            None => String::new()
-        };
-        let node_right = match &self.right {
-            Some(node) => format!("{}", node),
-            None => String::new(),
-        };
-        write!(f, 
-               "\n\n[BSTMap::Node @ {:p}]\
-                  \n      key: {:?}\
-                  \n    value: {:?}\
-                  \n left key: {}\
-                  \nright key: {}{}{}", 
-               self,
-               self.key, 
-               self.value, 
-               key_left, 
-               key_right,
-               node_left,
-               node_right)
+    // Our left child takes our place and we become its right child.
+    fn rotate_right(self: &mut NodeBox<T, V, A>) {
+        let mut left = self.left.take().unwrap();
+        self.left = left.right.take();
+        self.update();
+        std::mem::swap(self, &mut left);
+        self.right = Some(left);
+        self.update();
     }
 }
@@ -0,0 +1,723 @@
+use std::{borrow::Borrow, cmp::Ordering, fmt, iter::FusedIterator, mem, sync::Arc};
+use std::ops::{Bound, Index, RangeBounds};
+use crate::compare::{Compare, Natural};
+use crate::node::Neighbor;
+use crate::range::check_bounds;
+
+// Persistent alternative to BstMap.
+// Nodes are shared between maps through Arcs, and are never changed once
+// another map can see them. Inserting or removing copies only the Nodes
+// on the way down to the change, plus any a rotation touches, and links
+// the copies to the untouched subtrees of the original. Every other Node
+// stays shared, so each version costs O(log n) Nodes, and cloning a map
+// is just cloning the Arc at its root.
+//
+// Changes are made with Arc::make_mut, which copies a Node only if it is
+// shared. A map changing a tree it has all to itself, like one being
+// built by FromIterator, works in place.
+//
+// Otherwise the tree works like BstMap's: AVL balanced, with cached
+// heights and subtree sizes, rebalanced along a recorded path, and
+// ordered by the map's comparator. Every version made from a map
+// carries a clone of its comparator, so comparators should be cheap
+// to clone for cloning a map to stay O(1).
+
+pub type Link<T, V> = Option<Arc<PersistentNode<T, V>>>;
+// Raw pointer to a Link inside a tree a map has to itself.
+type LinkPtr<T, V> = *mut Link<T, V>;
+
+#[derive(Clone)]
+pub struct PersistentNode<T, V> {
+    pub key: T,
+    pub value: V,
+    pub left: Link<T, V>,
+    pub right: Link<T, V>,
+    pub height: u8,
+    pub size: usize,
+}
+
+/// A map implemented with a persistent binary search tree,
+/// whose versions share every node they have in common.
+///
+/// `insert` and `remove` leave the map alone and return a new one,
+/// in O(log n). Cloning a map is O(1), and maps can be shared
+/// across threads, so a snapshot can be handed out and kept around
+/// while the next version is being made.
+///
+/// Offers the lookup and iteration interface of `BstMap`. Like `BstMap`,
+/// keys can be ordered by a comparator, see `with_comparator`.
+///
+/// ```
+/// use bstmap::PersistentBstMap;
+///
+/// let v1 = PersistentBstMap::new().insert("port", 80).insert("workers", 4);
+/// let v2 = v1.insert("port", 8080).remove("workers");
+/// assert!(v1.get("port") == Some(&80) && v1.len() == 2);
+/// assert!(v2.get("port") == Some(&8080) && v2.len() == 1);
+/// ```
+pub struct PersistentBstMap<T, V, C = Natural> {
+    pub(crate) root: Link<T, V>,
+    len: usize,
+    // Decides which way every descent turns, see compare.rs.
+    cmp: C,
+}
+
+impl<T, V> PersistentBstMap<T, V> {
+    /// Creates an empty `PersistentBstMap`.
+    pub fn new() -> Self {
+        Self::with_comparator(Natural)
+    }
+}
+
+impl<T, V, C> PersistentBstMap<T, V, C> {
+    /// Creates an empty `PersistentBstMap` which orders its keys with
+    /// `cmp` instead of their `Ord` implementation.
+    ///
+    /// Every version made from map orders its keys with a clone of `cmp`.
+    ///
+    /// ```
+    /// # use bstmap::{PersistentBstMap, Reverse, Natural};
+    /// let map = PersistentBstMap::with_comparator(Reverse(Natural))
+    ///     .insert(1, 'a')
+    ///     .insert(3, 'c')
+    ///     .insert(2, 'b');
+    /// assert!(map.iter().map(|(k, _)| *k).eq([3, 2, 1]));
+    /// assert!(map.floor(&0) == Some((&1, &'a')));
+    /// ```
+    pub fn with_comparator(cmp: C) -> Self {
+        Self { root: None, len: 0, cmp }
+    }
+
+    /// Returns a reference to the comparator map orders its keys with.
+    pub fn comparator(&self) -> &C {
+        &self.cmp
+    }
+
+    /// Returns `true` if map is empty.
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Returns the number of entries in map.
+    pub fn len(&self) -> usize { self.len }
+
+    /// Returns `true` if both maps are versions which share their whole
+    /// tree, like a map and its clone. Maps holding equal entries
+    /// built separately don't.
+    ///
+    /// ```
+    /// # use bstmap::PersistentBstMap;
+    /// let map = PersistentBstMap::new().insert(1, "a");
+    /// assert!(map.ptr_eq(&map.clone()));
+    /// assert!(!map.ptr_eq(&map.insert(1, "a")));
+    /// ```
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns an `Iterator` over references to the contents of map,
+    /// yielding key/value pairs in ascending key order.
+    pub fn iter(&self) -> PersistentIter<'_, T, V> {
+        PersistentIter::new(&self.root, 0, self.len)
+    }
+
+    /// Returns an `Iterator` over contents of map starting at `index`
+    /// in sorted order. Finding the start runs in O(log n).
+    pub fn iter_from_index(&self, index: usize) -> PersistentIter<'_, T, V> {
+        PersistentIter::new(&self.root, index.min(self.len), self.len)
+    }
+
+}
+
+impl<T, V, C: Compare<T>> PersistentBstMap<T, V, C> {
+    /// Returns an `Iterator` over the entries of map whose keys
+    /// fall within `range`, in ascending key order.
+    ///
+    /// ```
+    /// # use bstmap::PersistentBstMap;
+    /// let map: PersistentBstMap<_, _> = (0..10).map(|i| (i, i * 10)).collect();
+    /// assert!(map.range(3..6).map(|(_, v)| *v).eq([30, 40, 50]));
+    /// assert!(map.range(..=2).rev().map(|(k, _)| *k).eq([2, 1, 0]));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if range `start > end`, or if range `start == end`
+    /// and both bounds are `Excluded`.
+    pub fn range<Q, R>(&self, range: R) -> PersistentIter<'_, T, V>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q> {
+
+        check_bounds(&range, &self.cmp);
+        // Subtree sizes turn both bounds into indices.
+        let start = match range.start_bound() {
+            Bound::Included(key) => self.rank(key),
+            Bound::Excluded(key) => self.rank(key) + self.get(key).is_some() as usize,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => self.rank(key) + self.get(key).is_some() as usize,
+            Bound::Excluded(key) => self.rank(key),
+            Bound::Unbounded => self.len,
+        };
+        PersistentIter::new(&self.root, start, end.max(start))
+    }
+
+    /// Returns `Some(&value)` associated with key,
+    /// or `None` if key wasn't found.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut link = self.root.as_deref();
+        while let Some(node) = link {
+            link = match self.cmp.compare(key, node.key.borrow()) {
+                Ordering::Greater => node.right.as_deref(),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Equal => return Some(&node.value),
+            };
+        }
+        None
+    }
+
+    /// Returns the key/value pair with the smallest key,
+    /// or `None` if map is empty.
+    pub fn first_key_value(&self) -> Option<(&T, &V)> {
+        self.get_index(0)
+    }
+
+    /// Returns the key/value pair with the largest key,
+    /// or `None` if map is empty.
+    pub fn last_key_value(&self) -> Option<(&T, &V)> {
+        self.get_index(self.len.checked_sub(1)?)
+    }
+
+    /// Returns the key/value pair with the largest key less than or equal to `key`,
+    /// or `None` if there is no such key.
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        self.neighbor(key, Neighbor::Floor)
+    }
+
+    /// Returns the key/value pair with the smallest key greater than or equal to `key`,
+    /// or `None` if there is no such key.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        self.neighbor(key, Neighbor::Ceiling)
+    }
+
+    /// Returns the key/value pair with the largest key strictly less than `key`,
+    /// or `None` if there is no such key.
+    pub fn lower<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        self.neighbor(key, Neighbor::Lower)
+    }
+
+    /// Returns the key/value pair with the smallest key strictly greater than `key`,
+    /// or `None` if there is no such key.
+    pub fn higher<Q>(&self, key: &Q) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        self.neighbor(key, Neighbor::Higher)
+    }
+
+    /// Returns the key/value pair at `index` in sorted order,
+    /// counting from 0, or `None` if `index >= len`.
+    /// Runs in O(log n).
+    pub fn get_index(&self, mut index: usize) -> Option<(&T, &V)> {
+        let mut link = self.root.as_deref();
+        while let Some(node) = link {
+            let left_size = link_size(&node.left);
+            link = match index.cmp(&left_size) {
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Equal => return Some((&node.key, &node.value)),
+                // Skip past the left subtree and ourselves.
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    node.right.as_deref()
+                }
+            };
+        }
+        None
+    }
+
+    /// Returns the number of keys in map strictly less than `key`.
+    /// If `key` is in map, this is its index in sorted order.
+    /// Runs in O(log n).
+    pub fn rank<Q>(&self, key: &Q) -> usize
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut rank = 0;
+        let mut link = self.root.as_deref();
+        while let Some(node) = link {
+            link = match self.cmp.compare(key, node.key.borrow()) {
+                Ordering::Greater => {
+                    rank += link_size(&node.left) + 1;
+                    node.right.as_deref()
+                }
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Equal => return rank + link_size(&node.left),
+            };
+        }
+        rank
+    }
+
+    // Same as Node::neighbor.
+    fn neighbor<Q>(&self, key: &Q, neighbor: Neighbor) -> Option<(&T, &V)>
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut best = None;
+        let mut link = self.root.as_deref();
+        while let Some(node) = link {
+            let ordering = self.cmp.compare(node.key.borrow(), key);
+            let direction = if neighbor.accepts(ordering) {
+                best = Some((&node.key, &node.value));
+                neighbor.towards_key()
+            } else {
+                neighbor.towards_key().reverse()
+            };
+            link = match direction {
+                Ordering::Greater => node.right.as_deref(),
+                _ => node.left.as_deref(),
+            };
+        }
+        best
+    }
+}
+
+impl<T: Clone, V: Clone, C: Compare<T> + Clone> PersistentBstMap<T, V, C> {
+    /// Returns a new map holding the entries of map plus `key`/`value`,
+    /// with `value` replacing the existing value if key exists.
+    /// Map itself is left unchanged. Runs in O(log n).
+    ///
+    /// ```
+    /// # use bstmap::PersistentBstMap;
+    /// let empty = PersistentBstMap::new();
+    /// let one = empty.insert("ten", 10);
+    /// assert!(empty.is_empty() && one[&"ten"] == 10);
+    /// ```
+    pub fn insert(&self, key: T, value: V) -> Self {
+        let mut map = self.clone();
+        map.insert_in_place(key, value);
+        map
+    }
+
+    /// Returns a new map holding the entries of map without the one
+    /// matching key. Map itself is left unchanged. Runs in O(log n).
+    ///
+    /// If key isn't found, the new map shares map's whole tree.
+    pub fn remove<Q>(&self, key: &Q) -> Self
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let mut map = self.clone();
+        // Don't copy the way down to a key that isn't there.
+        if self.get(key).is_some() {
+            map.remove_in_place(key);
+        }
+        map
+    }
+
+    // Inserts into the tree, copying the Nodes it has to change
+    // if they are shared.
+    fn insert_in_place(&mut self, key: T, value: V) {
+        let (path, link) = seek_mut(&mut self.root, &key, &self.cmp);
+        // Safety: path and link were just recorded from self.root.
+        unsafe {
+            match &mut *link {
+                Some(node) => Arc::make_mut(node).value = value,
+                None => {
+                    *link = Some(Arc::new(PersistentNode {
+                        key,
+                        value,
+                        left: None,
+                        right: None,
+                        height: 1,
+                        size: 1,
+                    }));
+                    self.len += 1;
+                    retrace(path);
+                }
+            }
+        }
+    }
+
+    // Removes the Node matching key, which must be in the tree,
+    // copying the Nodes it has to change if they are shared.
+    //
+    // Same strategy as Node::unlink: with two children, the Node's pair
+    // is swapped with that of its successor, which is removed instead.
+    fn remove_in_place<Q>(&mut self, key: &Q)
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized {
+
+        let (mut path, link) = seek_mut(&mut self.root, key, &self.cmp);
+        // Safety: path and link were just recorded from self.root, and
+        // every Node on the way was made our own. The successor's is made
+        // our own before anything is changed under it.
+        unsafe {
+            let node: *mut PersistentNode<T, V> = Arc::make_mut((*link).as_mut().unwrap());
+            if (*node).left.is_some() && (*node).right.is_some() {
+                path.push(link);
+                let mut successor_link: LinkPtr<T, V> = &mut (*node).right;
+                loop {
+                    let successor = Arc::make_mut((*successor_link).as_mut().unwrap());
+                    if successor.left.is_none() { break }
+                    path.push(successor_link);
+                    successor_link = &mut successor.left;
+                }
+                let mut successor = (*successor_link).take().unwrap();
+                let successor = Arc::make_mut(&mut successor);
+                *successor_link = successor.right.take();
+                std::mem::swap(&mut (*node).key, &mut successor.key);
+                std::mem::swap(&mut (*node).value, &mut successor.value);
+            } else {
+                let mut removed = (*link).take().unwrap();
+                let removed = Arc::make_mut(&mut removed);
+                *link = removed.left.take().or_else(|| removed.right.take());
+            }
+            self.len -= 1;
+            retrace(path);
+        }
+    }
+}
+
+// Walks down from root to the Node matching key, or the empty link where
+// it would go, making every Node on the way our own.
+//
+// Returns the links passed through, top first, and that link. Copying
+// a Node only moves the Arcs to its children, so links recorded
+// further up stay where they are.
+fn seek_mut<T, V, Q, C>(root: &mut Link<T, V>, key: &Q, cmp: &C) -> (Vec<LinkPtr<T, V>>, LinkPtr<T, V>)
+        where T: Clone + Borrow<Q>, V: Clone, C: Compare<Q>, Q: ?Sized {
+
+    let mut path = Vec::new();
+    let mut link: LinkPtr<T, V> = root;
+    // Safety: link always points either at root or at a child link
+    // of a Node we own, reached through root, which we borrow mutably.
+    unsafe {
+        while let Some(node) = &mut *link {
+            let node = Arc::make_mut(node);
+            let next: LinkPtr<T, V> = match cmp.compare(key, node.key.borrow()) {
+                Ordering::Greater => &mut node.right,
+                Ordering::Less => &mut node.left,
+                Ordering::Equal => break,
+            };
+            path.push(link);
+            link = next;
+        }
+    }
+    (path, link)
+}
+
+// Rebalances every link in path, deepest first.
+//
+// Safety: every link in path must still be in place, and hold
+// a Node we own.
+unsafe fn retrace<T: Clone, V: Clone>(path: Vec<LinkPtr<T, V>>) {
+    for &link in path.iter().rev() {
+        if let Some(node) = &mut *link {
+            rebalance(node);
+        }
+    }
+}
+
+fn link_height<T, V>(link: &Link<T, V>) -> u8 {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn link_size<T, V>(link: &Link<T, V>) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+impl<T, V> PersistentNode<T, V> {
+    fn update(&mut self) {
+        self.height = 1 + link_height(&self.left).max(link_height(&self.right));
+        self.size = 1 + link_size(&self.left) + link_size(&self.right);
+    }
+
+    fn balance_factor(&self) -> i16 {
+        link_height(&self.left) as i16 - link_height(&self.right) as i16
+    }
+}
+
+// Same as Node::rebalance. node must be our own, its
+// children are copied if a rotation has to change them.
+fn rebalance<T: Clone, V: Clone>(node: &mut Arc<PersistentNode<T, V>>) {
+    Arc::make_mut(node).update();
+    match node.balance_factor() {
+        2 => {
+            let inner = Arc::make_mut(node);
+            let left = inner.left.as_mut().unwrap();
+            if left.balance_factor() < 0 {
+                rotate_left(left);
+            }
+            rotate_right(node);
+        }
+        -2 => {
+            let inner = Arc::make_mut(node);
+            let right = inner.right.as_mut().unwrap();
+            if right.balance_factor() > 0 {
+                rotate_right(right);
+            }
+            rotate_left(node);
+        }
+        _ => (),
+    }
+}
+
+// Our right child takes our place and we become its left child.
+fn rotate_left<T: Clone, V: Clone>(node: &mut Arc<PersistentNode<T, V>>) {
+    let inner = Arc::make_mut(node);
+    let mut right = inner.right.take().unwrap();
+    let right_inner = Arc::make_mut(&mut right);
+    inner.right = right_inner.left.take();
+    inner.update();
+    std::mem::swap(node, &mut right);
+    let inner = Arc::make_mut(node);
+    inner.left = Some(right);
+    inner.update();
+}
+
+// Our left child takes our place and we become its right child.
+fn rotate_right<T: Clone, V: Clone>(node: &mut Arc<PersistentNode<T, V>>) {
+    let inner = Arc::make_mut(node);
+    let mut left = inner.left.take().unwrap();
+    let left_inner = Arc::make_mut(&mut left);
+    inner.left = left_inner.right.take();
+    inner.update();
+    std::mem::swap(node, &mut left);
+    let inner = Arc::make_mut(node);
+    inner.right = Some(left);
+    inner.update();
+}
+
+// Drops the Nodes in stack and under them. Only Nodes no other map
+// shares are freed. They are taken apart one by one, instead of
+// dropping recursively.
+fn free<T, V>(mut stack: Vec<Arc<PersistentNode<T, V>>>) {
+    while let Some(node) = stack.pop() {
+        if let Some(mut node) = Arc::into_inner(node) {
+            stack.extend(node.left.take());
+            stack.extend(node.right.take());
+        }
+    }
+}
+
+impl<T, V, C> Drop for PersistentBstMap<T, V, C> {
+    fn drop(&mut self) {
+        free(self.root.take().into_iter().collect());
+    }
+}
+
+impl<T, V, C: Clone> Clone for PersistentBstMap<T, V, C> {
+    // Shares the whole tree. O(1) if cloning the comparator is.
+    fn clone(&self) -> Self {
+        Self { root: self.root.clone(), len: self.len, cmp: self.cmp.clone() }
+    }
+}
+
+impl<T, V, C: Default> Default for PersistentBstMap<T, V, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+impl<T: fmt::Debug, V: fmt::Debug, C> fmt::Debug for PersistentBstMap<T, V, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Like BstMap, maps compare by their pairs in key order.
+
+impl<T: PartialEq, V: PartialEq, C> PartialEq for PersistentBstMap<T, V, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && (self.ptr_eq(other) || self.iter().eq(other.iter()))
+    }
+}
+
+impl<T: Eq, V: Eq, C> Eq for PersistentBstMap<T, V, C> {}
+
+impl<T: Clone, V: Clone, C: Compare<T> + Clone + Default> FromIterator<(T, V)> for PersistentBstMap<T, V, C> {
+    // The map has its tree to itself the whole time,
+    // so nothing gets copied.
+    fn from_iter<I: IntoIterator<Item = (T, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        for (key, value) in iter {
+            map.insert_in_place(key, value);
+        }
+        map
+    }
+}
+
+impl<T, V, C, Q> Index<&Q> for PersistentBstMap<T, V, C>
+        where T: Borrow<Q>, C: Compare<T> + Compare<Q>, Q: ?Sized {
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &Self::Output {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+/// `Iterator` over references to the contents of a `PersistentBstMap`,
+/// yielding key/value pairs in ascending key order.
+pub struct PersistentIter<'a, T, V> {
+    front: Vec<&'a PersistentNode<T, V>>,
+    back: Vec<&'a PersistentNode<T, V>>,
+    len: usize,
+}
+
+impl<'a, T, V> PersistentIter<'a, T, V> {
+    // Iterator over the pairs from index start up to, but
+    // not including, index end.
+    fn new(root: &'a Link<T, V>, start: usize, end: usize) -> Self {
+        let mut iter = PersistentIter { front: Vec::new(), back: Vec::new(), len: end - start };
+        if start == end { return iter }
+
+        // Nodes before start are skipped along with their left subtrees.
+        let mut index = start;
+        let mut link = root.as_deref();
+        while let Some(node) = link {
+            let left_size = link_size(&node.left);
+            if index <= left_size {
+                iter.front.push(node);
+                if index == left_size { break }
+                link = node.left.as_deref();
+            } else {
+                index -= left_size + 1;
+                link = node.right.as_deref();
+            }
+        }
+
+        // Nodes after the last one are skipped along with their right subtrees.
+        let mut index = end - 1;
+        let mut link = root.as_deref();
+        while let Some(node) = link {
+            let left_size = link_size(&node.left);
+            if index >= left_size {
+                iter.back.push(node);
+                if index == left_size { break }
+                index -= left_size + 1;
+                link = node.right.as_deref();
+            } else {
+                link = node.left.as_deref();
+            }
+        }
+        iter
+    }
+}
+
+impl<'a, T, V> Iterator for PersistentIter<'a, T, V> {
+    type Item = (&'a T, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        let node = self.front.pop().unwrap();
+        let mut link = node.right.as_deref();
+        while let Some(next) = link {
+            self.front.push(next);
+            link = next.left.as_deref();
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T, V> DoubleEndedIterator for PersistentIter<'_, T, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 { return None }
+        self.len -= 1;
+
+        let node = self.back.pop().unwrap();
+        let mut link = node.left.as_deref();
+        while let Some(next) = link {
+            self.back.push(next);
+            link = next.right.as_deref();
+        }
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<T, V> ExactSizeIterator for PersistentIter<'_, T, V> {}
+impl<T, V> FusedIterator for PersistentIter<'_, T, V> {}
+
+impl<'a, T, V, C> IntoIterator for &'a PersistentBstMap<T, V, C> {
+    type Item = (&'a T, &'a V);
+    type IntoIter = PersistentIter<'a, T, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Clone, V: Clone, C> IntoIterator for PersistentBstMap<T, V, C> {
+    type Item = (T, V);
+    type IntoIter = PersistentIntoIter<T, V>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        let mut iter = PersistentIntoIter { stack: Vec::new(), len: self.len };
+        iter.push_left(self.root.take());
+        iter
+    }
+}
+
+/// `Iterator` over the contents of a `PersistentBstMap`, yielding
+/// key/value pairs in ascending key order.
+///
+/// Pairs held only by the map being iterated are moved out,
+/// while pairs shared with other versions are cloned.
+pub struct PersistentIntoIter<T, V> {
+    // Nodes whose pairs are still to come, next one on top. A Node
+    // we had to ourselves had its left child taken on the way down.
+    stack: Vec<Arc<PersistentNode<T, V>>>,
+    len: usize,
+}
+
+impl<T, V> PersistentIntoIter<T, V> {
+    // Pushes link and the leftmost path under it.
+    fn push_left(&mut self, mut link: Link<T, V>) {
+        while let Some(mut node) = link {
+            link = match Arc::get_mut(&mut node) {
+                Some(inner) => inner.left.take(),
+                None => node.left.clone(),
+            };
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<T: Clone, V: Clone> Iterator for PersistentIntoIter<T, V> {
+    type Item = (T, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.len -= 1;
+
+        let (key, value, right) = match Arc::try_unwrap(node) {
+            Ok(PersistentNode { key, value, right, .. }) => (key, value, right),
+            Err(node) => (node.key.clone(), node.value.clone(), node.right.clone()),
+        };
+        self.push_left(right);
+        Some((key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T: Clone, V: Clone> ExactSizeIterator for PersistentIntoIter<T, V> {}
+impl<T: Clone, V: Clone> FusedIterator for PersistentIntoIter<T, V> {}
+
+impl<T, V> Drop for PersistentIntoIter<T, V> {
+    fn drop(&mut self) {
+        free(mem::take(&mut self.stack));
+    }
+}
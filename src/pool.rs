@@ -0,0 +1,151 @@
+use std::{alloc::Layout, error::Error, fmt};
+use crate::allocator::{self, Allocator, NodeBox, Spare};
+use crate::node::Node;
+
+// Node pool for BstMap.
+// Holds spare, uninitialized Node allocations, so inserts can take one
+// instead of going to the allocator. The pool is filled by reserving
+// capacity, and removes hand their Node's allocation back to it for as
+// long as the map is below that capacity, the same way a Vec keeps its
+// buffer around.
+//
+// Allocating through the pool can also be made fallible, reporting
+// allocation failure as a TryReserveError instead of aborting.
+
+/// The error returned when a `BstMap` fails to allocate memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TryReserveError {
+    kind: TryReserveErrorKind,
+}
+
+/// Details of a `TryReserveError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryReserveErrorKind {
+    /// The requested capacity exceeds the maximum a map can hold.
+    CapacityOverflow,
+    /// The allocator returned an error.
+    AllocError {
+        /// The layout of the allocation that failed.
+        layout: Layout,
+    },
+}
+
+impl TryReserveError {
+    /// Returns details about the allocation that failed.
+    pub fn kind(&self) -> TryReserveErrorKind {
+        self.kind.clone()
+    }
+}
+
+impl From<TryReserveErrorKind> for TryReserveError {
+    fn from(kind: TryReserveErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+impl fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")?;
+        match self.kind {
+            TryReserveErrorKind::CapacityOverflow => {
+                f.write_str(" because the computed capacity exceeded the map's maximum")
+            }
+            TryReserveErrorKind::AllocError { .. } => {
+                f.write_str(" because the memory allocator returned an error")
+            }
+        }
+    }
+}
+
+impl Error for TryReserveError {}
+
+#[derive(Debug)]
+pub struct NodePool<T, V, A: Allocator + Clone> {
+    // Spares are allocated in alloc, like every other Node.
+    alloc: A,
+    spares: Vec<Spare<T, V, A>>,
+    // Nodes the map asked to hold without allocating,
+    // live ones and spares together.
+    capacity: usize,
+}
+
+impl<T, V, A: Allocator + Clone> NodePool<T, V, A> {
+    pub fn new(alloc: A) -> Self {
+        Self {
+            alloc,
+            spares: Vec::new(),
+            capacity: 0,
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    // Number of spare Node allocations.
+    pub fn spares(&self) -> usize {
+        self.spares.len()
+    }
+
+    // Makes room for additional more Nodes on top of the len live ones.
+    pub fn reserve(&mut self, len: usize, additional: usize) {
+        self.capacity = self.capacity.max(len.checked_add(additional).expect("capacity overflow"));
+        self.spares.reserve(additional.saturating_sub(self.spares.len()));
+        while self.spares.len() < additional {
+            self.spares.push(allocator::spare(&self.alloc));
+        }
+    }
+
+    // Same as reserve, but reports allocation failure instead of aborting.
+    // Spares allocated before a failure are kept.
+    pub fn try_reserve(&mut self, len: usize, additional: usize) -> Result<(), TryReserveError> {
+        let capacity = len.checked_add(additional).ok_or(TryReserveErrorKind::CapacityOverflow)?;
+        let missing = additional.saturating_sub(self.spares.len());
+        let layout = Layout::array::<Spare<T, V, A>>(self.spares.len() + missing)
+            .map_err(|_| TryReserveErrorKind::CapacityOverflow)?;
+        self.spares.try_reserve(missing).map_err(|_| TryReserveErrorKind::AllocError { layout })?;
+        self.capacity = self.capacity.max(capacity);
+
+        while self.spares.len() < additional {
+            let spare = allocator::try_spare(&self.alloc)?;
+            self.spares.push(spare);
+        }
+        Ok(())
+    }
+
+    // Drops every spare.
+    pub fn shrink(&mut self) {
+        self.spares = Vec::new();
+        self.capacity = 0;
+    }
+
+    // Boxes node, in a spare allocation if there is one.
+    pub fn alloc(&mut self, node: Node<T, V, A>) -> NodeBox<T, V, A> {
+        match self.spares.pop() {
+            Some(spare) => allocator::fill(spare, node),
+            None => allocator::boxed(node, &self.alloc),
+        }
+    }
+
+    // Makes sure the next alloc doesn't need to allocate,
+    // reporting allocation failure instead of aborting.
+    pub fn try_reserve_one(&mut self) -> Result<(), TryReserveError> {
+        if !self.spares.is_empty() { return Ok(()) }
+        let layout = Layout::new::<Spare<T, V, A>>();
+        self.spares.try_reserve(1).map_err(|_| TryReserveErrorKind::AllocError { layout })?;
+        let spare = allocator::try_spare(&self.alloc)?;
+        self.spares.push(spare);
+        Ok(())
+    }
+
+    // Takes the key and value out of an unlinked Node, keeping its
+    // allocation as a spare if the len live Nodes are below capacity.
+    pub fn recycle(&mut self, node: NodeBox<T, V, A>, len: usize) -> (T, V) {
+        let (Node { key, value, .. }, spare) = allocator::empty(node);
+        // Pushing must not allocate, or removing could abort.
+        if len + self.spares.len() < self.capacity && self.spares.len() < self.spares.capacity() {
+            self.spares.push(spare);
+        }
+        (key, value)
+    }
+}
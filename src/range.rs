@@ -0,0 +1,243 @@
+use std::{borrow::Borrow, cmp::Ordering, iter::FusedIterator, marker::PhantomData};
+use std::ops::{Bound, RangeBounds};
+use std::ptr::{self, NonNull};
+use crate::allocator::{Allocator, Global};
+use crate::compare::Compare;
+use crate::node::{Node, NodeLink};
+use crate::iter::{push_left, push_right, push_left_mut, push_right_mut};
+
+// Range iterators for BstMap.
+// Same idea as the iterators in iter.rs: one stack per end, each holding
+// a single spine of the tree. The difference is in how the stacks are
+// first filled. Descending towards a bound, any Node outside of it is
+// skipped along with the whole subtree on its far side, so none of the
+// tree outside the range is ever visited.
+//
+// There is no len to count down, so the two ends instead watch for the
+// moment they are both about to yield the same Node.
+
+// Is key on the inside of the start bound?
+fn after_start<Q: ?Sized, C: Compare<Q>>(key: &Q, start: Bound<&Q>, cmp: &C) -> bool {
+    match start {
+        Bound::Included(start) => cmp.compare(key, start) != Ordering::Less,
+        Bound::Excluded(start) => cmp.compare(key, start) == Ordering::Greater,
+        Bound::Unbounded => true,
+    }
+}
+
+// Is key on the inside of the end bound?
+fn before_end<Q: ?Sized, C: Compare<Q>>(key: &Q, end: Bound<&Q>, cmp: &C) -> bool {
+    match end {
+        Bound::Included(end) => cmp.compare(key, end) != Ordering::Greater,
+        Bound::Excluded(end) => cmp.compare(key, end) == Ordering::Less,
+        Bound::Unbounded => true,
+    }
+}
+
+// Panics on ranges that can't contain anything, the same way
+// the standard library's ordered collections do.
+pub fn check_bounds<Q: ?Sized, C: Compare<Q>, R: RangeBounds<Q>>(range: &R, cmp: &C) {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Excluded(start), Bound::Excluded(end))
+                if cmp.compare(start, end) == Ordering::Equal => {
+            panic!("range start and end are equal and excluded in BstMap")
+        }
+        (Bound::Included(start) | Bound::Excluded(start),
+         Bound::Included(end) | Bound::Excluded(end))
+                if cmp.compare(start, end) == Ordering::Greater => {
+            panic!("range start is greater than range end in BstMap")
+        }
+        _ => (),
+    }
+}
+
+/// `Iterator` over references to a sub-range of a `BstMap`,
+/// yielding key/value pairs in ascending key order.
+///
+/// Constructed with `BstMap::range`.
+pub struct Range<'a, T, V, A: Allocator + Clone = Global> {
+    front: Vec<&'a Node<T, V, A>>,
+    back: Vec<&'a Node<T, V, A>>,
+}
+
+impl<'a, T, V, A: Allocator + Clone> Range<'a, T, V, A> {
+    pub(crate) fn new<Q, C, R>(head: &'a NodeLink<T, V, A>, range: R, cmp: &C) -> Self
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q> {
+
+        check_bounds(&range, cmp);
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+
+        // Going down to the start, Nodes before it are skipped
+        // together with their left subtrees.
+        let mut node = head.as_deref();
+        while let Some(next) = node {
+            if after_start(next.key.borrow(), range.start_bound(), cmp) {
+                front.push(next);
+                node = next.left.as_deref();
+            } else {
+                node = next.right.as_deref();
+            }
+        }
+
+        // Going down to the end, Nodes after it are skipped
+        // together with their right subtrees.
+        let mut node = head.as_deref();
+        while let Some(next) = node {
+            if before_end(next.key.borrow(), range.end_bound(), cmp) {
+                back.push(next);
+                node = next.right.as_deref();
+            } else {
+                node = next.left.as_deref();
+            }
+        }
+
+        // If the first Node in range comes after the last one,
+        // there was nothing between the bounds.
+        match (front.last(), back.last()) {
+            (Some(first), Some(last))
+                    if cmp.compare(first.key.borrow(), last.key.borrow()) != Ordering::Greater => {
+                Range { front, back }
+            }
+            _ => Range { front: Vec::new(), back: Vec::new() },
+        }
+    }
+
+    // Both ends have met, nothing is left.
+    fn finish(&mut self) {
+        self.front.clear();
+        self.back.clear();
+    }
+}
+
+impl<'a, T, V, A: Allocator + Clone> Iterator for Range<'a, T, V, A> {
+    type Item = (&'a T, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.front.pop()?;
+        if self.back.last().is_some_and(|last| ptr::eq(node, *last)) {
+            self.finish();
+        } else {
+            push_left(&mut self.front, node.right.as_deref());
+        }
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<T, V, A: Allocator + Clone> DoubleEndedIterator for Range<'_, T, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let node = self.back.pop()?;
+        if self.front.last().is_some_and(|first| ptr::eq(node, *first)) {
+            self.finish();
+        } else {
+            push_right(&mut self.back, node.left.as_deref());
+        }
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<T, V, A: Allocator + Clone> FusedIterator for Range<'_, T, V, A> {}
+
+/// `Iterator` over a sub-range of a `BstMap` with mutable values,
+/// yielding key/value pairs in ascending key order.
+///
+/// Constructed with `BstMap::range_mut`.
+//
+// Holds raw pointers for the same reason IterMut does.
+pub struct RangeMut<'a, T, V, A: Allocator + Clone = Global> {
+    front: Vec<NonNull<Node<T, V, A>>>,
+    back: Vec<NonNull<Node<T, V, A>>>,
+    marker: PhantomData<&'a mut Node<T, V, A>>,
+}
+
+impl<'a, T, V, A: Allocator + Clone> RangeMut<'a, T, V, A> {
+    pub(crate) fn new<Q, C, R>(head: &'a mut NodeLink<T, V, A>, range: R, cmp: &C) -> Self
+            where T: Borrow<Q>, C: Compare<Q>, Q: ?Sized, R: RangeBounds<Q> {
+
+        check_bounds(&range, cmp);
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+        let head: *mut NodeLink<T, V, A> = head;
+
+        // Safety: head is borrowed mutably for 'a, and nothing has
+        // been handed out yet.
+        unsafe {
+            // See Range::new.
+            let mut link = head;
+            while let Some(node) = &mut *link {
+                let node = NonNull::new_unchecked(ptr::addr_of_mut!(**node));
+                if after_start((*node.as_ptr()).key.borrow(), range.start_bound(), cmp) {
+                    front.push(node);
+                    link = ptr::addr_of_mut!((*node.as_ptr()).left);
+                } else {
+                    link = ptr::addr_of_mut!((*node.as_ptr()).right);
+                }
+            }
+
+            let mut link = head;
+            while let Some(node) = &mut *link {
+                let node = NonNull::new_unchecked(ptr::addr_of_mut!(**node));
+                if before_end((*node.as_ptr()).key.borrow(), range.end_bound(), cmp) {
+                    back.push(node);
+                    link = ptr::addr_of_mut!((*node.as_ptr()).right);
+                } else {
+                    link = ptr::addr_of_mut!((*node.as_ptr()).left);
+                }
+            }
+
+            match (front.last(), back.last()) {
+                (Some(first), Some(last))
+                        if cmp.compare((*first.as_ptr()).key.borrow(), (*last.as_ptr()).key.borrow())
+                            != Ordering::Greater => {
+                    RangeMut { front, back, marker: PhantomData }
+                }
+                _ => RangeMut { front: Vec::new(), back: Vec::new(), marker: PhantomData },
+            }
+        }
+    }
+
+    // Both ends have met, nothing is left.
+    fn finish(&mut self) {
+        self.front.clear();
+        self.back.clear();
+    }
+}
+
+impl<'a, T, V, A: Allocator + Clone> Iterator for RangeMut<'a, T, V, A> {
+    type Item = (&'a T, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.front.pop()?;
+        // Safety: the ends stop as soon as they meet, so this Node
+        // hasn't been yielded yet. The map stays borrowed for 'a.
+        unsafe {
+            if self.back.last() == Some(&node) {
+                self.finish();
+            } else {
+                push_left_mut(&mut self.front, ptr::addr_of_mut!((*node.as_ptr()).right));
+            }
+            Some((&(*node.as_ptr()).key, &mut (*node.as_ptr()).value))
+        }
+    }
+}
+
+impl<T, V, A: Allocator + Clone> DoubleEndedIterator for RangeMut<'_, T, V, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let node = self.back.pop()?;
+        // Safety: see next.
+        unsafe {
+            if self.front.last() == Some(&node) {
+                self.finish();
+            } else {
+                push_right_mut(&mut self.back, ptr::addr_of_mut!((*node.as_ptr()).left));
+            }
+            Some((&(*node.as_ptr()).key, &mut (*node.as_ptr()).value))
+        }
+    }
+}
+
+impl<T, V, A: Allocator + Clone> FusedIterator for RangeMut<'_, T, V, A> {}
+
+// RangeMut hands out the same references as &'a mut BstMap would.
+unsafe impl<T: Sync, V: Send, A: Allocator + Clone> Send for RangeMut<'_, T, V, A> {}
+unsafe impl<T: Sync, V: Sync, A: Allocator + Clone> Sync for RangeMut<'_, T, V, A> {}
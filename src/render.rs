@@ -0,0 +1,354 @@
+use std::fmt::{self, Debug, Write};
+use crate::BstMap;
+use crate::allocator::Allocator;
+use crate::node::Node;
+
+// Tree renderer for BstMap, used by its Display impl.
+// Rendering happens in two passes. The tree is first flattened into a
+// list of labelled Items, which is where depth limits and label options
+// are applied. Each layout then only has to work with that list. Both
+// passes keep explicit stacks, so deep trees can't overflow the stack.
+
+/// Characters used to draw the branches of a tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Box-drawing characters: `├──`, `└──`, `┌─┴─┐`
+    Unicode,
+    /// Plain ASCII: `|--`, `` `-- ``, `+-+-+`
+    Ascii,
+}
+
+/// How a tree is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Root on the first line, with the children of every node on
+    /// the lines below it, indented one level further. Left child
+    /// first, and a missing child is drawn as a placeholder so the
+    /// two can be told apart. Works for trees of any size.
+    Sideways,
+    /// Root on the first line, with each level of the tree on the
+    /// lines below it. Best for small trees, as wide levels make
+    /// for very long lines.
+    TopDown,
+}
+
+/// Draws the tree behind a `BstMap`.
+///
+/// ```
+/// use bstmap::{BstMap, Renderer, Layout, Charset};
+///
+/// let mut map = BstMap::new();
+/// for key in [2, 1, 3] {
+///     map.insert(key, key * 10);
+/// }
+///
+/// let renderer = Renderer::new().values(false);
+/// assert!(renderer.render(&map) == "2\n├── 1\n└── 3");
+///
+/// let renderer = Renderer::new()
+///     .layout(Layout::TopDown)
+///     .charset(Charset::Ascii);
+/// assert!(renderer.render(&map) == [
+///     "      2: 20",
+///     "  +-----+-----+",
+///     "1: 10       3: 30",
+/// ].join("\n"));
+/// ```
+///
+/// `Display` for `BstMap` uses `Renderer::default()`.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    charset: Charset,
+    layout: Layout,
+    max_depth: Option<usize>,
+    values: bool,
+    pointers: bool,
+}
+
+// A Node, flattened and labelled.
+struct Item {
+    label: String,
+    depth: usize,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+// Every string a layout needs from a Charset.
+struct Glyphs {
+    branch: &'static str,
+    last: &'static str,
+    pipe: &'static str,
+    blank: &'static str,
+    missing: &'static str,
+    more: &'static str,
+    horizontal: char,
+    left_corner: char,
+    right_corner: char,
+    both: char,
+    only_left: char,
+    only_right: char,
+}
+
+const UNICODE: Glyphs = Glyphs {
+    branch: "├── ",
+    last: "└── ",
+    pipe: "│   ",
+    blank: "    ",
+    missing: "∅",
+    more: " …",
+    horizontal: '─',
+    left_corner: '┌',
+    right_corner: '┐',
+    both: '┴',
+    only_left: '┘',
+    only_right: '└',
+};
+
+const ASCII: Glyphs = Glyphs {
+    branch: "|-- ",
+    last: "`-- ",
+    pipe: "|   ",
+    blank: "    ",
+    missing: "-",
+    more: " ...",
+    horizontal: '-',
+    left_corner: '+',
+    right_corner: '+',
+    both: '+',
+    only_left: '+',
+    only_right: '+',
+};
+
+impl Renderer {
+    /// Creates a `Renderer` with the default options:
+    /// Unicode, sideways, no depth limit, values shown, pointers hidden.
+    pub fn new() -> Self {
+        Self {
+            charset: Charset::Unicode,
+            layout: Layout::Sideways,
+            max_depth: None,
+            values: true,
+            pointers: false,
+        }
+    }
+
+    /// Sets the characters branches are drawn with.
+    pub fn charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    /// Sets how the tree is laid out.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Draws at most `depth` levels of the tree, counting the root
+    /// as the first. Nodes whose children were cut off are marked
+    /// with a trailing ellipsis. The root is always drawn.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth.max(1));
+        self
+    }
+
+    /// Sets whether values are drawn next to their keys.
+    pub fn values(mut self, show: bool) -> Self {
+        self.values = show;
+        self
+    }
+
+    /// Sets whether the address of every node is drawn.
+    pub fn pointers(mut self, show: bool) -> Self {
+        self.pointers = show;
+        self
+    }
+
+    /// Renders `map` into a `String`. There is no trailing newline.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Debug` implementation of a key or value returns an
+    /// error. Use `write` to get the error back instead.
+    pub fn render<T, V, C, A>(&self, map: &BstMap<T, V, C, A>) -> String
+            where T: Debug, V: Debug, A: Allocator + Clone {
+
+        let mut out = String::new();
+        // Writing to a String can't fail, so only Debug can.
+        self.write(map, &mut out).expect("a Debug implementation returned an error");
+        out
+    }
+
+    /// Renders `map` into `out`. There is no trailing newline.
+    ///
+    /// Fails if writing to `out` fails, or if the `Debug` implementation
+    /// of a key or value returns an error.
+    pub fn write<T, V, C, A, W>(&self, map: &BstMap<T, V, C, A>, out: &mut W) -> fmt::Result
+            where T: Debug, V: Debug, A: Allocator + Clone, W: Write {
+
+        let glyphs = match self.charset {
+            Charset::Unicode => &UNICODE,
+            Charset::Ascii => &ASCII,
+        };
+        let Some(head) = &map.head else {
+            return out.write_str("(empty)");
+        };
+
+        let items = self.flatten(head, glyphs)?;
+        match self.layout {
+            Layout::Sideways => Renderer::sideways(&items, glyphs, out),
+            Layout::TopDown => Renderer::top_down(&items, glyphs, out),
+        }
+    }
+
+    // Text drawn for node. cut marks a Node whose children are hidden.
+    // Fails if the Debug implementation of the key or value does.
+    fn label<T, V, A>(&self, node: &Node<T, V, A>, cut: bool, glyphs: &Glyphs) -> Result<String, fmt::Error>
+            where T: Debug, V: Debug, A: Allocator + Clone {
+
+        let mut label = String::new();
+        write!(label, "{:?}", node.key)?;
+        if self.values {
+            write!(label, ": {:?}", node.value)?;
+        }
+        if self.pointers {
+            write!(label, " @ {:p}", node)?;
+        }
+        if cut {
+            label.push_str(glyphs.more);
+        }
+        Ok(label)
+    }
+
+    // Flattens the tree into Items in pre-order, so the root is first.
+    fn flatten<T, V, A>(&self, head: &Node<T, V, A>, glyphs: &Glyphs) -> Result<Vec<Item>, fmt::Error>
+            where T: Debug, V: Debug, A: Allocator + Clone {
+
+        let mut items: Vec<Item> = Vec::new();
+        // (node, depth, parent item and whether we're its left child)
+        let mut stack = vec![(head, 0, None::<(usize, bool)>)];
+        while let Some((node, depth, parent)) = stack.pop() {
+            let has_children = node.left.is_some() || node.right.is_some();
+            let cut = has_children && self.max_depth.is_some_and(|max| depth + 1 >= max);
+
+            let index = items.len();
+            items.push(Item {
+                label: self.label(node, cut, glyphs)?,
+                depth,
+                left: None,
+                right: None,
+            });
+            match parent {
+                Some((parent, true)) => items[parent].left = Some(index),
+                Some((parent, false)) => items[parent].right = Some(index),
+                None => (),
+            }
+
+            if cut { continue }
+            // Right is pushed first so left pops first.
+            if let Some(ref right) = node.right { stack.push((right, depth + 1, Some((index, false)))); }
+            if let Some(ref left) = node.left { stack.push((left, depth + 1, Some((index, true)))); }
+        }
+        Ok(items)
+    }
+
+    fn sideways<W: Write>(items: &[Item], glyphs: &Glyphs, out: &mut W) -> fmt::Result {
+        // (item or missing child, prefix for its line, prefix for its children)
+        let mut stack = vec![(Some(0), String::new(), String::new())];
+        let mut first = true;
+        while let Some((index, line_prefix, child_prefix)) = stack.pop() {
+            if !first { out.write_char('\n')?; }
+            first = false;
+
+            let Some(index) = index else {
+                write!(out, "{}{}", line_prefix, glyphs.missing)?;
+                continue
+            };
+            let item = &items[index];
+            write!(out, "{}{}", line_prefix, item.label)?;
+
+            // Leaves don't draw placeholders for both missing children.
+            if item.left.is_none() && item.right.is_none() { continue }
+            stack.push((item.right,
+                        format!("{}{}", child_prefix, glyphs.last),
+                        format!("{}{}", child_prefix, glyphs.blank)));
+            stack.push((item.left,
+                        format!("{}{}", child_prefix, glyphs.branch),
+                        format!("{}{}", child_prefix, glyphs.pipe)));
+        }
+        Ok(())
+    }
+
+    fn top_down<W: Write>(items: &[Item], glyphs: &Glyphs, out: &mut W) -> fmt::Result {
+        // Labels are placed left to right in sorted order, one column
+        // apart, which keeps every label clear of all the others.
+        let width = |item: &Item| item.label.chars().count();
+        let mut column = vec![0; items.len()];
+        let mut next_column = 0;
+        let mut stack = Vec::new();
+        let mut index = Some(0);
+        loop {
+            while let Some(i) = index {
+                stack.push(i);
+                index = items[i].left;
+            }
+            let Some(i) = stack.pop() else { break };
+            column[i] = next_column;
+            next_column += width(&items[i]) + 1;
+            index = items[i].right;
+        }
+        let center = |i: usize| column[i] + width(&items[i]) / 2;
+
+        // Pre-order keeps each level's Items in left to right order.
+        let depth = items.iter().map(|item| item.depth).max().unwrap_or(0);
+        let mut levels = vec![Vec::new(); depth + 1];
+        for (i, item) in items.iter().enumerate() {
+            levels[item.depth].push(i);
+        }
+
+        let mut first = true;
+        for level in levels {
+            let mut labels: Vec<char> = Vec::new();
+            let mut branches: Vec<char> = Vec::new();
+            for i in level {
+                let item = &items[i];
+                labels.resize(column[i], ' ');
+                labels.extend(item.label.chars());
+
+                let middle = center(i);
+                let joint = match (item.left, item.right) {
+                    (None, None) => continue,
+                    (Some(_), Some(_)) => glyphs.both,
+                    (Some(_), None) => glyphs.only_left,
+                    (None, Some(_)) => glyphs.only_right,
+                };
+                if let Some(left) = item.left {
+                    branches.resize(center(left), ' ');
+                    branches.push(glyphs.left_corner);
+                    branches.resize(middle, glyphs.horizontal);
+                }
+                branches.resize(middle, ' ');
+                branches.push(joint);
+                if let Some(right) = item.right {
+                    branches.resize(center(right), glyphs.horizontal);
+                    branches.push(glyphs.right_corner);
+                }
+            }
+
+            if !first { out.write_char('\n')?; }
+            first = false;
+            out.write_str(&labels.into_iter().collect::<String>())?;
+            if !branches.is_empty() {
+                out.write_char('\n')?;
+                out.write_str(&branches.into_iter().collect::<String>())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fmt, marker::PhantomData};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use crate::{BstMap, Entry};
+use crate::allocator::Allocator;
+use crate::compare::{Compare, Natural};
+
+// serde support for BstMap, behind the serde feature.
+// A BstMap is serialized as a map with its keys in ascending order, and
+// deserialized from any map. Like insert, deserializing lets the last
+// value for a key win by default; DuplicateKeys::Error rejects the input
+// instead.
+
+/// What to do when deserializing a map which holds the same key more than once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Keep the value that came last. This is what `Deserialize` does.
+    LastWins,
+    /// Fail with an error.
+    Error,
+}
+
+impl<T: Serialize, V: Serialize, C, A: Allocator + Clone> Serialize for BstMap<T, V, C, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, T, V, C> Deserialize<'de> for BstMap<T, V, C>
+        where T: Deserialize<'de>, V: Deserialize<'de>, C: Compare<T> + Default {
+
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BstMap::deserialize_with_policy(deserializer, DuplicateKeys::LastWins)
+    }
+}
+
+impl<'de, T, V, C> BstMap<T, V, C>
+        where T: Deserialize<'de>, V: Deserialize<'de>, C: Compare<T> + Default {
+
+    /// Deserializes a `BstMap`, handling duplicate keys as set by `duplicates`.
+    ///
+    /// ```
+    /// # use bstmap::{BstMap, DuplicateKeys};
+    /// let json = r#"{"a": 1, "a": 2}"#;
+    /// let mut de = serde_json::Deserializer::from_str(json);
+    /// let map = BstMap::<String, u32>::deserialize_with_policy(&mut de, DuplicateKeys::LastWins);
+    /// assert!(map.unwrap()["a"] == 2);
+    ///
+    /// let mut de = serde_json::Deserializer::from_str(json);
+    /// let map = BstMap::<String, u32>::deserialize_with_policy(&mut de, DuplicateKeys::Error);
+    /// assert!(map.is_err());
+    /// ```
+    pub fn deserialize_with_policy<D>(deserializer: D, duplicates: DuplicateKeys) -> Result<Self, D::Error>
+            where D: Deserializer<'de> {
+
+        deserializer.deserialize_map(BstMapVisitor {
+            duplicates,
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Deserializes a `BstMap`, failing on duplicate keys.
+/// Meant for `#[serde(deserialize_with = "bstmap::deserialize_unique")]`.
+///
+/// ```
+/// # use bstmap::BstMap;
+/// // Within a struct deriving Deserialize:
+/// //     #[serde(deserialize_with = "bstmap::deserialize_unique")]
+/// //     limits: BstMap<String, u32>,
+/// let mut de = serde_json::Deserializer::from_str(r#"{"a": 1, "a": 2}"#);
+/// let limits: Result<BstMap<String, u32>, _> = bstmap::deserialize_unique(&mut de);
+/// assert!(limits.is_err());
+/// ```
+pub fn deserialize_unique<'de, D, T, V, C>(deserializer: D) -> Result<BstMap<T, V, C>, D::Error>
+        where D: Deserializer<'de>, T: Deserialize<'de>, V: Deserialize<'de>, C: Compare<T> + Default {
+
+    BstMap::deserialize_with_policy(deserializer, DuplicateKeys::Error)
+}
+
+struct BstMapVisitor<T, V, C = Natural> {
+    duplicates: DuplicateKeys,
+    marker: PhantomData<BstMap<T, V, C>>,
+}
+
+impl<'de, T, V, C> Visitor<'de> for BstMapVisitor<T, V, C>
+        where T: Deserialize<'de>, V: Deserialize<'de>, C: Compare<T> + Default {
+
+    type Value = BstMap<T, V, C>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut map = BstMap::default();
+        while let Some((key, value)) = access.next_entry()? {
+            match map.entry(key) {
+                Entry::Vacant(entry) => { entry.insert(value); }
+                Entry::Occupied(_) if self.duplicates == DuplicateKeys::Error => {
+                    return Err(de::Error::custom("duplicate key in BstMap"));
+                }
+                Entry::Occupied(mut entry) => { entry.insert(value); }
+            }
+        }
+        Ok(map)
+    }
+}
@@ -0,0 +1,369 @@
+use std::{cmp::Ordering, error::Error, fmt, io::{self, BufWriter, Read, Write}};
+use crate::allocator::Allocator;
+use crate::compare::Compare;
+use crate::node::{Node, NodeLink};
+
+// Native binary snapshots of a BstMap.
+//
+// Layout, all integers little endian:
+//
+//   magic     b"BSTM"
+//   version   u16
+//   len       u64
+//   entries   len times key then value, in ascending key order
+//   checksum  u64, FNV-1a of every byte before it
+//
+// Entries are written by walking the tree in order, so reading them back
+// gives a sorted stream, which is built straight into a balanced tree
+// without comparing or rebalancing anything.
+
+const MAGIC: [u8; 4] = *b"BSTM";
+const VERSION: u16 = 1;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// Largest number of elements reserved up front from a length read out of
+// a snapshot, so a corrupt length can't make us allocate all memory.
+const MAX_PREALLOCATE: usize = 4096;
+
+/// Error returned by `BstMap::read_snapshot`.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading failed, or the snapshot ended early.
+    Io(io::Error),
+    /// The input does not start like a snapshot.
+    BadMagic,
+    /// The snapshot was written by an unknown version of the format.
+    UnsupportedVersion(u16),
+    /// The checksum does not match the contents.
+    ChecksumMismatch,
+    /// Keys are not in strictly ascending order.
+    Unsorted,
+    /// A key or value could not be decoded.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "failed to read snapshot: {}", err),
+            SnapshotError::BadMagic => f.write_str("not a BstMap snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            SnapshotError::ChecksumMismatch => f.write_str("snapshot checksum mismatch"),
+            SnapshotError::Unsorted => f.write_str("snapshot keys are not in ascending order"),
+            SnapshotError::Invalid(what) => write!(f, "invalid snapshot data: {}", what),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+/// Types that can be written into a `BstMap` snapshot.
+pub trait Encode {
+    /// Writes `self` to `w`.
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+}
+
+/// Types that can be read back out of a `BstMap` snapshot.
+pub trait Decode: Sized {
+    /// Reads a value written by the matching `Encode` impl from `r`.
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError>;
+}
+
+macro_rules! impl_number {
+    ($($number:ty),*) => {$(
+        impl Encode for $number {
+            fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+        }
+
+        impl Decode for $number {
+            fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+                let mut bytes = [0; std::mem::size_of::<$number>()];
+                r.read_exact(&mut bytes)?;
+                Ok(<$number>::from_le_bytes(bytes))
+            }
+        }
+    )*};
+}
+
+impl_number!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+// usize and isize are always written as 64 bits, so snapshots
+// can be moved between platforms.
+
+impl Encode for usize {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (*self as u64).encode(w)
+    }
+}
+
+impl Decode for usize {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        usize::try_from(u64::decode(r)?).map_err(|_| SnapshotError::Invalid("usize out of range"))
+    }
+}
+
+impl Encode for isize {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (*self as i64).encode(w)
+    }
+}
+
+impl Decode for isize {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        isize::try_from(i64::decode(r)?).map_err(|_| SnapshotError::Invalid("isize out of range"))
+    }
+}
+
+impl Encode for bool {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (*self as u8).encode(w)
+    }
+}
+
+impl Decode for bool {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        match u8::decode(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Invalid("bool out of range")),
+        }
+    }
+}
+
+impl Encode for char {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (*self as u32).encode(w)
+    }
+}
+
+impl Decode for char {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        char::from_u32(u32::decode(r)?).ok_or(SnapshotError::Invalid("char out of range"))
+    }
+}
+
+impl Encode for () {
+    fn encode<W: Write>(&self, _w: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Decode for () {
+    fn decode<R: Read>(_r: &mut R) -> Result<Self, SnapshotError> {
+        Ok(())
+    }
+}
+
+// Strings and Vecs are prefixed with their length.
+
+impl Encode for str {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.len().encode(w)?;
+        w.write_all(self.as_bytes())
+    }
+}
+
+impl Encode for String {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.as_str().encode(w)
+    }
+}
+
+impl Decode for String {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        let len = u64::decode(r)?;
+        // Reads no more than there is, however large len claims to be.
+        let mut bytes = Vec::new();
+        r.take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        String::from_utf8(bytes).map_err(|_| SnapshotError::Invalid("string is not UTF-8"))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.len().encode(w)?;
+        for item in self {
+            item.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        let len = usize::decode(r)?;
+        let mut items = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+        for _ in 0..len {
+            items.push(T::decode(r)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Some(item) => {
+                1u8.encode(w)?;
+                item.encode(w)
+            }
+            None => 0u8.encode(w),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        match u8::decode(r)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(r)?)),
+            _ => Err(SnapshotError::Invalid("option tag out of range")),
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.0.encode(w)?;
+        self.1.encode(w)
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, SnapshotError> {
+        Ok((A::decode(r)?, B::decode(r)?))
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (**self).encode(w)
+    }
+}
+
+// Continues an FNV-1a hash over bytes.
+pub(crate) fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// Checksums everything written through it.
+struct HashWriter<W> {
+    inner: W,
+    hash: u64,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hash = fnv1a(self.hash, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Checksums everything read through it.
+struct HashReader<R> {
+    inner: R,
+    hash: u64,
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hash = fnv1a(self.hash, &buf[..read]);
+        Ok(read)
+    }
+}
+
+// Writes the len Nodes under head as a snapshot.
+pub(crate) fn write<T, V, A, W>(head: &NodeLink<T, V, A>, len: usize, w: W) -> io::Result<()>
+        where T: Encode, V: Encode, A: Allocator + Clone, W: Write {
+
+    let mut w = HashWriter { inner: BufWriter::new(w), hash: FNV_OFFSET };
+    w.write_all(&MAGIC)?;
+    VERSION.encode(&mut w)?;
+    len.encode(&mut w)?;
+
+    // In order walk, the same as IterRef.
+    let mut stack = Vec::new();
+    let mut node = head.as_deref();
+    loop {
+        while let Some(next) = node {
+            stack.push(next);
+            node = next.left.as_deref();
+        }
+        let Some(next) = stack.pop() else { break };
+        next.key.encode(&mut w)?;
+        next.value.encode(&mut w)?;
+        node = next.right.as_deref();
+    }
+
+    let hash = w.hash;
+    let mut w = w.inner;
+    hash.encode(&mut w)?;
+    w.flush()
+}
+
+// Reads a snapshot back into a tree boxed in alloc, and its len.
+// Keys have to be in ascending order by cmp.
+pub(crate) fn read<T, V, C, A, R>(r: R, cmp: &C, alloc: &A) -> Result<(NodeLink<T, V, A>, usize), SnapshotError>
+        where T: Decode, V: Decode, C: Compare<T>, A: Allocator + Clone, R: Read {
+
+    let mut r = HashReader { inner: r, hash: FNV_OFFSET };
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = u16::decode(&mut r)?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let len = usize::decode(&mut r)?;
+    let mut pairs = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+    for _ in 0..len {
+        let key = T::decode(&mut r)?;
+        let value = V::decode(&mut r)?;
+        pairs.push((key, value));
+    }
+
+    // Ordering is only checked once the contents are known to be intact,
+    // so corruption is reported as such.
+    let hash = r.hash;
+    if u64::decode(&mut r.inner)? != hash {
+        return Err(SnapshotError::ChecksumMismatch);
+    }
+    if !pairs.windows(2).all(|pair| cmp.compare(&pair[0].0, &pair[1].0) == Ordering::Less) {
+        return Err(SnapshotError::Unsorted);
+    }
+
+    Ok((Node::from_sorted(pairs, alloc), len))
+}